@@ -0,0 +1,6651 @@
+//! scriptr ‑ fast launcher for Rust single‑file packages (`cargo -Zscript`)
+//!
+//! Besides the `scriptr` binary, the crate can be used as a library: a [`Launcher`] builds a
+//! script through the same cache the command line uses and hands back its binary, ready to run.
+#![forbid(unsafe_code)]
+
+use anyhow::{Context, Result};
+use blake3::Hasher;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use dirs::cache_dir;
+// File locking goes through fs2 throughout. Since Rust 1.89 `File` has inherent methods of its
+// own, and its `unlock` would shadow fs2's, so that one is always called as `FileExt::unlock`.
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    io::{BufRead, BufReader, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitCode, ExitStatus, Output, Stdio},
+    sync::{
+        Mutex, OnceLock, PoisonError, TryLockError,
+        atomic::{AtomicI32, AtomicU32, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const NAME: &str = "scriptr";
+
+/// Whether scriptr's own output and cargo's are colored; unset (no color) until options are parsed.
+static COLOR: OnceLock<bool> = OnceLock::new();
+
+fn color() -> bool {
+    COLOR.get().copied().unwrap_or(false)
+}
+
+/// The cargo scriptr spawns; plain `cargo` from PATH unless overridden with `--cargo`.
+static CARGO: OnceLock<PathBuf> = OnceLock::new();
+
+fn cargo_program() -> &'static Path {
+    CARGO.get().map_or(Path::new("cargo"), PathBuf::as_path)
+}
+
+/// How scriptr's own status lines are written; text until options are parsed.
+static LOG_FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+/// The form of scriptr's status lines on stderr.
+#[derive(Clone, Copy, PartialEq, Debug, Default, ValueEnum)]
+enum LogFormat {
+    /// `[scriptr] message`
+    #[default]
+    Text,
+    /// One JSON object per line: `{"level":"debug","msg":"...","phase":"hash"}`
+    Json,
+}
+
+fn log_format() -> LogFormat {
+    LOG_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Where `--log-file` sends scriptr's status lines and cargo's warnings instead of stderr.
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Append `text` to the `--log-file`, each line prefixed with the current time, or else print
+/// it to stderr as is.
+fn write_diagnostics(text: &str) {
+    let Some(file) = LOG_FILE.get() else {
+        eprint!("{text}");
+        return;
+    };
+    let stamp = utc_timestamp(SystemTime::now());
+    let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+    for line in text.lines() {
+        // A log file that stops taking writes isn't worth failing the run over.
+        writeln!(file, "{stamp} {line}").ok();
+    }
+}
+
+/// `time` as an RFC 3339 UTC timestamp with milliseconds, e.g. `2023-11-14T22:13:20.000Z`.
+fn utc_timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since the epoch (Howard Hinnant's `civil_from_days`).
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        rem / 3_600,
+        rem / 60 % 60,
+        rem % 60,
+        since.subsec_millis()
+    )
+}
+
+/// How much a status line matters: `debug` lines only appear with `--verbose`.
+#[derive(Clone, Copy)]
+enum Level {
+    Debug,
+    Info,
+    Error,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Info => "info",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// Write one of scriptr's own status lines to stderr (or the `--log-file`), tagged with the
+/// `phase` it timed, if any.
+fn log(level: Level, phase: Option<&str>, msg: std::fmt::Arguments) {
+    if let Some(file) = LOG_FILE.get() {
+        let now = SystemTime::now();
+        let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+        match log_format() {
+            LogFormat::Text => writeln!(file, "{} [{NAME}] {msg}", utc_timestamp(now)),
+            LogFormat::Json => {
+                let mut line = serde_json::json!({
+                    "ts": utc_timestamp(now),
+                    "level": level.name(),
+                    "msg": msg.to_string(),
+                });
+                if let Some(phase) = phase {
+                    line["phase"] = phase.into();
+                }
+                writeln!(file, "{line}")
+            }
+        }
+        .ok();
+        return;
+    }
+    match log_format() {
+        LogFormat::Text if color() => eprintln!("\x1b[36m[{NAME}]\x1b[0m {msg}"),
+        LogFormat::Text => eprintln!("[{NAME}] {msg}"),
+        LogFormat::Json => {
+            let mut line = serde_json::json!({ "level": level.name(), "msg": msg.to_string() });
+            if let Some(phase) = phase {
+                line["phase"] = phase.into();
+            }
+            eprintln!("{line}");
+        }
+    }
+}
+
+/// Print one of scriptr's `[scriptr] ...` status lines, one that shows unless `--quiet`.
+macro_rules! note {
+    ($($arg:tt)*) => {
+        log(Level::Info, None, format_args!($($arg)*))
+    };
+}
+
+/// Print a status line for `--verbose`; callers check that it was given.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        log(Level::Debug, None, format_args!($($arg)*))
+    };
+}
+
+/// Fast launcher for Rust single-file packages
+#[derive(Parser)]
+#[command(
+    version,
+    disable_version_flag = true,
+    about = "Fast launcher for Rust single-file packages",
+    long_about = "Run Rust single-file packages (cargo -Zscript) with millisecond cold starts by caching builds intelligently.",
+    after_help = r#"USAGE AS SHEBANG
+  Put scriptr in your script's shebang and declare Cargo front-matter at the top:
+
+    #!/usr/bin/env scriptr
+    ---
+    [dependencies]
+    clap = { version = "4.5", features = ["derive"] }
+    anyhow = "1.0"
+    ---
+    // normal Rust code follows
+
+  Notes
+  - The front-matter is standard Cargo.toml inlined between lines with just "---".
+    You can use typical sections like [package], [dependencies], [features], etc.
+  - File extension is optional. "hello" or "hello.rs" both work.
+  - When invoking scriptr directly, pass your script args after the path or after "--":
+      scriptr ./hello.rs -- arg1 arg2
+    That "--" is scriptr's even through the shebang: ./hello -- x passes just x,
+    so pass a leading "--" to the script as ./hello -- -- x.
+  - To pass flags in the shebang, use env -S (portable across macOS/Linux):
+      #!/usr/bin/env -S scriptr --debug
+  - To key cache by a stable global identifier instead of script path:
+      #!/usr/bin/env -S scriptr --id=123e4567-e89b-12d3-a456-426614174000
+
+EXAMPLES
+  Minimal script:
+
+    #!/usr/bin/env scriptr
+    ---
+    [dependencies]
+    clap = { version = "4.5", features = ["derive"] }
+    ---
+
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Args { name: String }
+
+    fn main() {
+        let args = Args::parse();
+        println!("Hello, {}!", args.name);
+    }
+
+  Run it:
+    chmod +x ./hello.rs
+    ./hello.rs World
+
+SUBCOMMANDS
+  scriptr run <SCRIPT> [ARGS]   Build if needed, then run (the same as scriptr <SCRIPT>)
+  scriptr build <SCRIPT>        Build and cache the script without running it
+
+CACHE
+  scriptr cache list            List cached scripts, their binaries, sizes and build times
+  scriptr cache info <SCRIPT>   Show the cached fingerprint(s) for one script
+  scriptr cache prune           Drop entries whose script or binary is gone
+
+  "run", "build" and "cache" are only subcommands as the first argument; run
+  a script named cache as ./cache.
+"#
+)]
+struct Opts {
+    /// Build in debug mode (default is release, unless --default-profile says otherwise)
+    #[arg(short = 'd', long, visible_alias = "no-release")]
+    debug: bool,
+
+    /// Profile to build when neither --debug, --small nor --profile picks one (or the
+    /// script's companion file); wins over `debug` in the config file
+    #[arg(
+        long,
+        value_name = "PROFILE",
+        value_enum,
+        env = "SCRIPTR_DEFAULT_PROFILE"
+    )]
+    default_profile: Option<DefaultProfile>,
+
+    /// Optimize the release build for size: opt-level "z", LTO, one codegen unit, stripped
+    #[arg(long, conflicts_with = "debug")]
+    small: bool,
+
+    /// Build with this cargo profile, e.g. one defined in the script's manifest
+    #[arg(long, value_name = "NAME", value_parser = parse_profile, conflicts_with_all = ["debug", "small"])]
+    profile: Option<Profile>,
+
+    /// Verbose output
+    #[arg(short = 'v', long)]
+    verbose: bool,
+
+    /// Print nothing of scriptr's own or cargo's, except build errors
+    #[arg(short = 'q', long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Force rebuild (ignore cache)
+    #[arg(short = 'f', long)]
+    force: bool,
+
+    /// Clean cache before building
+    #[arg(short = 'c', long)]
+    clean: bool,
+
+    /// Clean cache and exit (don't run)
+    #[arg(short = 'C', long)]
+    clean_only: bool,
+
+    /// Fail if --clean can't remove the cache, instead of warning and rebuilding regardless
+    #[arg(long, requires = "clean")]
+    strict_clean: bool,
+
+    /// Update dependencies before building (re-resolves git deps to latest commits)
+    #[arg(short = 'u', long)]
+    update: bool,
+
+    /// Use only hash for comparison (ignore mtime)
+    #[arg(short = 'H', long)]
+    hash_only: bool,
+
+    /// Treat a changed mtime as a change, rebuilding without hashing to check the contents
+    #[arg(long, conflicts_with = "hash_only")]
+    trust_mtime: bool,
+
+    /// Print the path of the (cached or freshly built) binary instead of running it
+    #[arg(short = 'p', long, conflicts_with = "clean_only")]
+    print_path: bool,
+
+    /// Copy the built binary into DIR, named after the script, instead of running it
+    #[arg(long, value_name = "DIR", conflicts_with_all = [
+        "print_path", "clean_only", "check", "clippy", "explain", "build_all", "watch",
+    ])]
+    install: Option<PathBuf>,
+
+    /// Type-check the script with `cargo check` and exit, without building or running it
+    #[arg(long, conflicts_with_all = ["print_path", "clean_only"])]
+    check: bool,
+
+    /// Lint the script with `cargo clippy` and exit, without building or running it
+    #[arg(long, conflicts_with_all = ["print_path", "clean_only", "check"])]
+    clippy: bool,
+
+    /// Print whether the cache would be used, and why, then exit without building or running
+    #[arg(long, conflicts_with_all = ["clean", "clean_only", "check", "clippy"])]
+    explain: bool,
+
+    /// Print the cargo command a rebuild would run (or, on a cache hit, the binary), then exit
+    #[arg(long, conflicts_with_all = [
+        "clean", "clean_only", "check", "clippy", "explain", "build_all", "watch", "print_path",
+        "install",
+    ])]
+    dry_run: bool,
+
+    /// Rebuild from scratch and check the cached binary is identical to the result, then exit
+    #[arg(long, conflicts_with_all = [
+        "clean", "clean_only", "check", "clippy", "explain", "dry_run", "build_all", "watch",
+        "print_path", "install", "no_cache", "update",
+    ])]
+    verify: bool,
+
+    /// Print the script's embedded Cargo manifest, then exit without building or running
+    #[arg(long, conflicts_with_all = [
+        "clean", "clean_only", "check", "clippy", "explain", "build_all", "watch", "print_path",
+        "install",
+    ])]
+    dump_manifest: bool,
+
+    /// Print the script's cache key, metadata path and build variant, then exit without building
+    #[arg(long, conflicts_with_all = [
+        "clean", "clean_only", "check", "clippy", "explain", "dump_manifest", "build_all",
+        "watch", "print_path", "install", "no_cache",
+    ])]
+    print_cache_key: bool,
+
+    /// Build (but don't run) the script and every path after it, then summarize the results
+    #[arg(long, conflicts_with_all = [
+        "clean_only", "check", "clippy", "explain", "watch", "print_path", "no_cache", "id",
+        "args_file", "env", "stdin_name",
+    ])]
+    build_all: bool,
+
+    /// Rebuild and rerun whenever the script changes
+    #[arg(short = 'w', long, conflicts_with_all = ["print_path", "clean_only", "check", "clippy"])]
+    watch: bool,
+
+    /// How often --watch polls the script for changes, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    watch_interval: u64,
+
+    /// Kill the script if it runs longer than SECS, exiting with 124
+    #[arg(long, value_name = "SECS", conflicts_with_all = [
+        "print_path", "install", "clean_only", "check", "clippy", "explain", "build_all", "watch",
+    ])]
+    time_limit: Option<u64>,
+
+    /// Rebuild if the script differs from its version at git revision REV, whatever its mtime
+    #[arg(long, value_name = "REV", conflicts_with_all = ["eval", "build_all"])]
+    since: Option<String>,
+
+    /// Write how the binary was obtained to PATH as JSON before running it (cache hit or not)
+    #[arg(long, value_name = "PATH", conflicts_with_all = [
+        "clean_only", "check", "clippy", "explain", "build_all", "watch",
+    ])]
+    status_file: Option<PathBuf>,
+
+    /// When to color scriptr's and cargo's output
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Write scriptr's own status lines as text or as one JSON object per line
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Append scriptr's status lines and cargo's warnings to PATH, timestamped, instead of
+    /// stderr; implies --verbose
+    #[arg(long, value_name = "PATH", conflicts_with = "quiet")]
+    log_file: Option<PathBuf>,
+
+    /// Forward cargo's JSON build messages to stdout, followed by a scriptr status line
+    #[arg(long)]
+    json: bool,
+
+    /// Print the compiler's warnings when a build succeeds, not just its errors when one fails
+    #[arg(long, conflicts_with_all = ["quiet", "json"])]
+    show_warnings: bool,
+
+    /// Toolchain to build with (overrides any rust-toolchain file; default is nightly)
+    #[arg(long, value_name = "NAME")]
+    toolchain: Option<String>,
+
+    /// Pass --locked to cargo: fail if the script's cached Cargo.lock needs updating
+    #[arg(long)]
+    locked: bool,
+
+    /// Pass --offline to cargo: build only from dependencies already downloaded; part of the cache key
+    #[arg(long)]
+    offline: bool,
+
+    /// Pass --frozen to cargo: --locked and --offline together; part of the cache key
+    #[arg(long)]
+    frozen: bool,
+
+    /// Run cargo outside the current directory, so no surrounding project's Cargo config applies
+    #[arg(long)]
+    isolated: bool,
+
+    /// Let cargo run at most N build jobs at once (default: CARGO_BUILD_JOBS, else one per CPU)
+    #[arg(short = 'j', long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: Option<u32>,
+
+    /// Enable the script's FEATURES (comma- or space-separated; repeatable); part of the cache key
+    #[arg(short = 'F', long, value_name = "FEATURES")]
+    features: Vec<String>,
+
+    /// Enable all of the script's features; part of the cache key
+    #[arg(long)]
+    all_features: bool,
+
+    /// Don't enable the script's default features; part of the cache key
+    #[arg(long)]
+    no_default_features: bool,
+
+    /// Flags for rustc, appended to any RUSTFLAGS in the environment; part of the cache key
+    #[arg(long, value_name = "FLAGS", allow_hyphen_values = true)]
+    rustflags: Option<String>,
+
+    /// Extra argument for `cargo build` (repeatable), e.g. --cargo-arg=--features=foo
+    #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+    cargo_arg: Vec<String>,
+
+    /// Directory for scriptr's cache metadata (default: the platform cache dir)
+    #[arg(long, value_name = "DIR", env = "SCRIPTR_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// The cargo to build with (default: `cargo` from PATH)
+    #[arg(long, value_name = "PATH", env = "SCRIPTR_CARGO")]
+    cargo: Option<PathBuf>,
+
+    /// Retry a build this many times if it fails with a transient error (e.g. "text file busy")
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    build_retries: u32,
+
+    /// Remember a failed build, and repeat its errors without rebuilding until the script changes
+    #[arg(long, conflicts_with = "no_cache")]
+    cache_failures: bool,
+
+    /// Build in a throwaway directory, without reading or writing any cache metadata
+    #[arg(long, conflicts_with_all = ["clean", "clean_only"])]
+    no_cache: bool,
+
+    /// Share one binary between scripts with identical contents and build settings
+    #[arg(long, env = "SCRIPTR_DEDUP")]
+    dedup: bool,
+
+    /// Build every script into one target dir under the cache, sharing dependency builds
+    #[arg(long, env = "SCRIPTR_SHARED_TARGET")]
+    shared_target: bool,
+
+    /// Run and record the binary where cargo built it, not a copy under the cache's bin/ dir
+    #[arg(long)]
+    raw_bin_path: bool,
+
+    /// Evict least-recently-used cache entries beyond this total size (e.g. 500M, 2G)
+    #[arg(long, value_name = "BYTES", env = "SCRIPTR_MAX_CACHE_SIZE", value_parser = parse_size)]
+    max_cache_size: Option<u64>,
+
+    /// Rebuild a cached binary built longer ago than this (e.g. 30m, 24h, 7d), even if unchanged
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    rebuild_if_older_than: Option<Duration>,
+
+    /// Digest used to fingerprint the script's contents
+    #[arg(long, value_name = "ALGO", value_enum, default_value_t = HashAlgo::Blake3)]
+    hash: HashAlgo,
+
+    /// The name the script sees as argv[0] (default: its file stem, as given)
+    #[arg(long, value_name = "NAME")]
+    exec_arg0: Option<OsString>,
+
+    /// Set an environment variable for the script (repeatable); doesn't affect the cache
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env)]
+    env: Vec<(String, String)>,
+
+    /// Run the script in DIR instead of the current directory; cargo still builds from here, and
+    /// the cache key is unaffected
+    #[arg(long, value_name = "DIR")]
+    run_in: Option<PathBuf>,
+
+    /// Append the lines of this file to the script's arguments, one argument per line
+    #[arg(long, value_name = "PATH")]
+    args_file: Option<PathBuf>,
+
+    /// Cross-compile for this target triple; a binary the host can't run has its path printed
+    #[arg(long, value_name = "TRIPLE")]
+    target: Option<String>,
+
+    /// Build as this Rust edition, whatever the script's manifest says; part of the cache key
+    #[arg(long, value_name = "YEAR", value_parser = ["2015", "2018", "2021", "2024"])]
+    edition: Option<String>,
+
+    /// Globally unique script identifier (uses this instead of script path for cache keying)
+    #[arg(long, value_name = "ID")]
+    id: Option<String>,
+
+    /// Key the cache by the script's contents instead of its path, so identical copies anywhere
+    /// share one entry; mtimes are never trusted then
+    #[arg(long, env = "SCRIPTR_CONTENT_KEYED", conflicts_with_all = ["id", "stdin_name"])]
+    content_keyed: bool,
+
+    /// Key the cache by the script's absolute path as given, without resolving symlinks
+    #[arg(long)]
+    no_canonicalize: bool,
+
+    /// Cache key for a script read from stdin, in place of its content (which must still match)
+    #[arg(long, value_name = "NAME", conflicts_with = "id")]
+    stdin_name: Option<String>,
+
+    /// Build and run CODE as the script; arguments after `--` go to it
+    #[arg(short = 'e', long, value_name = "CODE", conflicts_with_all = [
+        "script", "build_all", "watch", "install", "stdin_name",
+    ])]
+    eval: Option<String>,
+
+    /// With --eval, wrap CODE in `fn main() { ... }` unless it defines `fn main` itself
+    #[arg(long, requires = "eval")]
+    wrap: bool,
+
+    /// Print version; with --verbose, also the toolchain, cargo, rustc and cache scriptr would use
+    #[arg(short = 'V', long)]
+    version: bool,
+
+    /// Path to the Rust script (extension optional), or `-` to read it from stdin
+    #[arg(required_unless_present_any = ["version", "eval"])]
+    script: Option<PathBuf>,
+}
+
+/// The built-in profiles `--default-profile` can choose between.
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum DefaultProfile {
+    Debug,
+    Release,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ColorChoice {
+    /// Color when stderr is a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self, json: bool) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            // --json puts cargo's rendered diagnostics on stdout, so that has to be a terminal too.
+            Self::Auto => {
+                std::io::stderr().is_terminal() && (!json || std::io::stdout().is_terminal())
+            }
+        }
+    }
+}
+
+/// Persisted fingerprint of a script file and the settings it was built with.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Fingerprint {
+    // Nanoseconds since the epoch. Entries from versions that stored whole seconds (`mtime`)
+    // deserialize as 0 here, so they always fall through to the hash check.
+    #[serde(default)]
+    mtime_ns: u64,
+    hash: String, // hex digest, covering `modules` too
+    /// The algorithm that produced `hash`; entries from before it was recorded are all BLAKE3.
+    #[serde(default = "default_hash_algo")]
+    hash_algo: String,
+    /// Sibling files pulled in with `mod foo;`, and the companion file, so editing one invalidates
+    /// the cache.
+    #[serde(default)]
+    modules: Vec<ModuleStamp>,
+    // Entries written before these were recorded deserialize as "" and never match.
+    #[serde(default)]
+    profile: String, // Profile::name()
+    #[serde(default)]
+    toolchain: String, // Toolchain::cache_id()
+    #[serde(default)]
+    cargo_args: Vec<String>,
+    /// `--target` triple, or "" for the host, which is also what older entries were built for.
+    #[serde(default)]
+    target: String,
+    /// The effective `RUSTFLAGS`; older entries were built without any, as far as we know.
+    #[serde(default)]
+    rustflags: String,
+    /// Built with `--offline` or `--frozen`, which can resolve dependencies differently.
+    #[serde(default)]
+    offline: bool,
+    #[serde(default)]
+    frozen: bool,
+    /// Built from a neutral directory; see [`BuildSettings::isolated_dir`].
+    #[serde(default)]
+    isolated: bool,
+    /// [`Features::id`]; older entries were built with the default features.
+    #[serde(default)]
+    features: String,
+    /// [`registry_id`]; "" in older entries, which never matches.
+    #[serde(default)]
+    registry: String,
+    /// `--edition`, or "" for the script's own, which is what older entries were built with.
+    #[serde(default)]
+    edition: String,
+}
+
+impl Fingerprint {
+    /// Which of a script's cached builds this is; see [`variant_key`].
+    fn variant(&self) -> String {
+        variant_key(
+            &self.profile,
+            &self.toolchain,
+            &self.target,
+            &self.rustflags,
+            &self.features,
+        )
+    }
+}
+
+/// Name a build variant by the settings that tell its binaries apart, e.g.
+/// `release +nightly host af1349b9f5f9a1a6` (the last part hashes the `RUSTFLAGS`), with the
+/// [`Features::id`] after that unless it's the defaults. Builds that differ only by
+/// `--cargo-arg` share a variant, the newer replacing the older.
+fn variant_key(
+    profile: &str,
+    toolchain: &str,
+    target: &str,
+    rustflags: &str,
+    features: &str,
+) -> String {
+    let target = if target.is_empty() { "host" } else { target };
+    let flags = blake3::hash(rustflags.as_bytes()).to_hex();
+    let mut key = format!("{profile} {toolchain} {target} {}", &flags[..16]);
+    if !features.is_empty() {
+        key.push(' ');
+        key.push_str(features);
+    }
+    key
+}
+
+/// Which of a script's features a build enables: `--features`, `--all-features` and
+/// `--no-default-features`.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Features {
+    /// Sorted and deduplicated, so the order they're given in doesn't matter.
+    names: Vec<String>,
+    /// Everything, which makes `names` and `no_default` moot, as it does for cargo.
+    all: bool,
+    no_default: bool,
+}
+
+impl Features {
+    /// Features from the command line. Like cargo, each `--features` value may list several,
+    /// separated by commas or spaces; each must be a valid feature name, or `dep/feature`.
+    fn new(lists: &[String], all: bool, no_default: bool) -> Result<Self> {
+        let mut names = Vec::new();
+        for name in lists
+            .iter()
+            .flat_map(|list| list.split([',', ' ']))
+            .filter(|name| !name.is_empty())
+        {
+            let valid = |part: &str| {
+                part.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+                    && part
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || "_-+.".contains(c))
+            };
+            let parts: Vec<&str> = name.split('/').collect();
+            if parts.len() > 2 || !parts.iter().all(|part| valid(part)) {
+                anyhow::bail!("invalid feature name {name:?} in --features");
+            }
+            names.push(name.to_string());
+        }
+        names.sort();
+        names.dedup();
+        if all {
+            return Ok(Self {
+                all,
+                ..Self::default()
+            });
+        }
+        Ok(Self {
+            names,
+            all,
+            no_default,
+        })
+    }
+
+    /// How the fingerprint and variant key record the selection: empty for cargo's defaults,
+    /// otherwise e.g. `all-features` or `no-default-features,+cli,+color`.
+    fn id(&self) -> String {
+        if self.all {
+            return "all-features".to_string();
+        }
+        let no_default = self.no_default.then(|| "no-default-features".to_string());
+        let names = self.names.iter().map(|name| format!("+{name}"));
+        no_default
+            .into_iter()
+            .chain(names)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Add the matching cargo flags to `cmd`.
+    fn args(&self, cmd: &mut Command) {
+        if self.all {
+            cmd.arg("--all-features");
+            return;
+        }
+        if self.no_default {
+            cmd.arg("--no-default-features");
+        }
+        if !self.names.is_empty() {
+            cmd.arg("--features").arg(self.names.join(","));
+        }
+    }
+}
+
+fn default_hash_algo() -> String {
+    HashAlgo::Blake3.name().to_string()
+}
+
+/// The digest behind `Fingerprint::hash`. Both produce 32 bytes, so hashes look alike on disk.
+#[derive(Clone, Copy, PartialEq, Debug, ValueEnum)]
+enum HashAlgo {
+    Blake3,
+    Sha256,
+}
+
+impl HashAlgo {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Blake3 => "blake3",
+            Self::Sha256 => "sha256",
+        }
+    }
+
+    fn hasher(self) -> SourceHasher {
+        match self {
+            Self::Blake3 => SourceHasher::Blake3(Box::default()),
+            Self::Sha256 => SourceHasher::Sha256(Sha256::new()),
+        }
+    }
+}
+
+/// An in-progress digest for whichever [`HashAlgo`] was asked for.
+enum SourceHasher {
+    Blake3(Box<Hasher>),
+    Sha256(Sha256),
+}
+
+impl SourceHasher {
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+            Self::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// The lowercase hex digest.
+    fn finish(self) -> String {
+        match self {
+            Self::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Self::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+        }
+    }
+}
+
+/// A `mod` file's (or the companion file's) path and the mtime it had when the script was built.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct ModuleStamp {
+    path: PathBuf,
+    mtime_ns: u64,
+}
+
+/// Metadata stored between runs: each build of the script that's still cached.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(from = "StoredMeta")]
+struct Meta {
+    /// The script this entry was built from, resolved but not the stand-in cargo may be given
+    /// instead (see [`manifest_path`]); absent in entries from older versions.
+    #[serde(serialize_with = "serialize_script")]
+    script: Option<PathBuf>,
+    /// Builds keyed by [`Fingerprint::variant`], so that switching back and forth between, say,
+    /// debug and release finds both cached.
+    variants: BTreeMap<String, Variant>,
+}
+
+/// One cached build of a script.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Variant {
+    fp: Fingerprint,
+    /// Stored relative to the cache root when it's inside it, so the cache can be moved; see
+    /// [`read_meta`].
+    bin: PathBuf,
+    /// What built `bin`, from [`Job::builder_version`]; absent in entries from older versions.
+    #[serde(default)]
+    builder_version: Option<String>,
+    /// When `bin` was built, in seconds since the Unix epoch; absent in entries from older
+    /// versions.
+    #[serde(default)]
+    built_at: Option<u64>,
+    /// BLAKE3 of `bin` as it was built, for `--verify`; absent in entries from older versions.
+    #[serde(default)]
+    bin_hash: Option<String>,
+    // For `cache info` and `cache list` alone; absent in entries from older versions, and in
+    // builds reused by `--dedup` for `build_ms`, since nothing was built.
+    /// The size of the sources, the script with the files [`fingerprint_sources`] tracks for it.
+    #[serde(default)]
+    source_bytes: Option<u64>,
+    /// How long cargo took over the build, in milliseconds.
+    #[serde(default)]
+    build_ms: Option<u64>,
+    /// The size of `bin` when it was built.
+    #[serde(default)]
+    bin_bytes: Option<u64>,
+}
+
+/// [`Meta`] as found on disk: entries from before variants were tracked hold a single build.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredMeta {
+    Variants {
+        #[serde(default, deserialize_with = "deserialize_script")]
+        script: Option<PathBuf>,
+        variants: BTreeMap<String, Variant>,
+    },
+    Single {
+        fp: Box<Fingerprint>,
+        bin: PathBuf,
+        #[serde(default, deserialize_with = "deserialize_script")]
+        script: Option<PathBuf>,
+        #[serde(default)]
+        builder_version: Option<String>,
+    },
+}
+
+/// How [`Meta::script`] is written: as a string, or as its bytes when the path isn't UTF-8,
+/// which a JSON string can't hold.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredPath {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+fn serialize_script<S: serde::Serializer>(
+    script: &Option<PathBuf>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let stored = script.as_deref().map(|path| match path.to_str() {
+        Some(text) => StoredPath::Text(text.to_string()),
+        None => StoredPath::Bytes(path.as_os_str().as_encoded_bytes().to_vec()),
+    });
+    stored.serialize(serializer)
+}
+
+fn deserialize_script<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<PathBuf>, D::Error> {
+    Ok(
+        Option::<StoredPath>::deserialize(deserializer)?.map(|stored| match stored {
+            StoredPath::Text(text) => PathBuf::from(text),
+            #[cfg(unix)]
+            StoredPath::Bytes(bytes) => {
+                use std::os::unix::ffi::OsStringExt;
+                PathBuf::from(OsString::from_vec(bytes))
+            }
+            #[cfg(not(unix))]
+            StoredPath::Bytes(bytes) => PathBuf::from(String::from_utf8_lossy(&bytes).as_ref()),
+        }),
+    )
+}
+
+impl From<StoredMeta> for Meta {
+    fn from(stored: StoredMeta) -> Self {
+        match stored {
+            StoredMeta::Variants { script, variants } => Self { script, variants },
+            StoredMeta::Single {
+                fp,
+                bin,
+                script,
+                builder_version,
+            } => Self {
+                script,
+                variants: BTreeMap::from([(
+                    fp.variant(),
+                    Variant {
+                        fp: *fp,
+                        bin,
+                        builder_version,
+                        built_at: None,
+                        bin_hash: None,
+                        source_bytes: None,
+                        build_ms: None,
+                        bin_bytes: None,
+                    },
+                )]),
+            },
+        }
+    }
+}
+
+/// A build that failed, recorded by `--cache-failures` so it isn't retried for the same sources.
+#[derive(Serialize, Deserialize, Debug)]
+struct Failure {
+    fp: Fingerprint,
+    /// Cargo's exit code.
+    code: Option<i32>,
+    /// The diagnostics printed for the failure.
+    output: String,
+}
+
+/// Defaults from the user's config file; command-line flags (and their env vars) take precedence.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    debug: Option<bool>,
+    default_profile: Option<DefaultProfile>,
+    verbose: Option<bool>,
+    toolchain: Option<String>,
+    cache_dir: Option<PathBuf>,
+    cargo_args: Vec<String>,
+    content_keyed: Option<bool>,
+}
+
+impl Config {
+    /// Fill in whatever `opts` left at its built-in default.
+    fn apply(self, mut opts: Opts) -> Opts {
+        // Either one of these only sets the default, so a default from the environment wins.
+        opts.default_profile = opts.default_profile.or(self.default_profile);
+        opts.debug |= self.debug.unwrap_or(false) && opts.default_profile.is_none();
+        opts.verbose |= self.verbose.unwrap_or(false) && !opts.quiet;
+        opts.toolchain = opts.toolchain.or(self.toolchain);
+        opts.cache_dir = opts.cache_dir.or(self.cache_dir);
+        opts.content_keyed |= self.content_keyed.unwrap_or(false);
+        if opts.cargo_arg.is_empty() {
+            opts.cargo_arg = self.cargo_args;
+        }
+        opts
+    }
+}
+
+/// Per-script defaults from its companion file (see [`companion_path`]), which take precedence
+/// over the config file but not over the command line.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Companion {
+    profile: Option<String>,
+    toolchain: Option<String>,
+    features: Vec<String>,
+    env: BTreeMap<String, String>,
+    cargo_args: Vec<String>,
+}
+
+impl Companion {
+    /// Fill in whatever `opts` left at its built-in default, before the config file gets to.
+    fn apply(self, mut opts: Opts) -> Result<Opts> {
+        if opts.profile.is_none()
+            && !opts.debug
+            && !opts.small
+            && let Some(profile) = self.profile
+        {
+            opts.profile = Some(parse_profile(&profile).map_err(anyhow::Error::msg)?);
+        }
+        opts.toolchain = opts.toolchain.or(self.toolchain);
+        if opts.features.is_empty() && !opts.all_features {
+            opts.features = self.features;
+        }
+        // Ahead of the command line's, so that those win.
+        opts.env.splice(0..0, self.env);
+        if opts.cargo_arg.is_empty() {
+            opts.cargo_arg = self.cargo_args;
+        }
+        Ok(opts)
+    }
+}
+
+/// Where `script`'s companion file would be: beside it, named after it, as in
+/// `foo.rs.scriptr.toml`.
+fn companion_path(script: &Path) -> PathBuf {
+    let mut name = script.file_name().unwrap_or_default().to_os_string();
+    name.push(".scriptr.toml");
+    script.with_file_name(name)
+}
+
+/// Where the config file lives: `$SCRIPTR_CONFIG`, else `scriptr/config.toml` under
+/// `$XDG_CONFIG_HOME` or `~/.config`.
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("SCRIPTR_CONFIG") {
+        return Some(path.into());
+    }
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))?;
+    Some(base.join(NAME).join("config.toml"))
+}
+
+/// Read the config file, if there is one. A file that exists but doesn't parse is an error.
+fn load_config() -> Result<Option<(PathBuf, Config)>> {
+    let Some(path) = config_path() else {
+        return Ok(None);
+    };
+    Ok(read_toml(&path, "config")?.map(|config| (path, config)))
+}
+
+/// Read the companion file for the script at `script` (as given to scriptr), if it has one.
+/// Like the config file, one that exists but doesn't parse is an error.
+fn load_companion(script: &Path, canonical: bool) -> Result<Option<(PathBuf, Companion)>> {
+    // What can't be resolved isn't a file with a companion; the error about it comes later.
+    let Ok(script) = resolve_script(script, canonical) else {
+        return Ok(None);
+    };
+    let path = companion_path(&script);
+    Ok(read_toml(&path, "companion file")?.map(|companion| (path, companion)))
+}
+
+/// Parse the TOML file at `path`, or `None` if there isn't one; `what` names it in errors.
+fn read_toml<T: serde::de::DeserializeOwned>(path: &Path, what: &str) -> Result<Option<T>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err).with_context(|| format!("cannot read {what} {}", path.display()));
+        }
+    };
+    let value =
+        toml::from_str(&text).with_context(|| format!("invalid {what} {}", path.display()))?;
+    Ok(Some(value))
+}
+
+/// `scriptr completions <shell>`: print a shell completion script for scriptr's options.
+#[derive(Parser)]
+#[command(name = "completions", bin_name = "scriptr completions")]
+struct CompletionsOpts {
+    /// Shell to generate completions for
+    shell: clap_complete::Shell,
+}
+
+/// `scriptr cache <command>`: inspect and tidy the metadata cache.
+#[derive(Parser)]
+#[command(name = "cache", bin_name = "scriptr cache")]
+struct CacheOpts {
+    /// Directory for scriptr's cache metadata (default: the platform cache dir)
+    #[arg(long, value_name = "DIR", env = "SCRIPTR_CACHE_DIR", global = true)]
+    cache_dir: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// List each cached script with its binary, size and how long it took to build
+    List,
+    /// Show the cached fingerprint(s) for one script
+    Info {
+        /// Path to the script
+        script: PathBuf,
+    },
+    /// Remove entries whose binary or source script no longer exists
+    Prune,
+    /// Remove entries by age, total size or orphanhood, reporting the space reclaimed
+    #[command(group(
+        clap::ArgGroup::new("policy")
+            .args(["older_than", "max_size", "orphans"])
+            .required(true)
+            .multiple(true)
+    ))]
+    Gc {
+        /// Remove entries not used within this long (e.g. 30d, 12h)
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        older_than: Option<Duration>,
+        /// Then remove the least recently used entries until the cache fits this size (e.g. 2G)
+        #[arg(long, value_name = "BYTES", value_parser = parse_size)]
+        max_size: Option<u64>,
+        /// Remove entries whose source script or binaries no longer exist
+        #[arg(long)]
+        orphans: bool,
+        /// Print what would be removed, without removing it
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Remove everything scriptr keeps in the cache: every entry, binary and build dir
+    Clear {
+        /// Don't ask first (nor is there a question when not run from a terminal)
+        #[arg(short = 'y', long)]
+        yes: bool,
+    },
+}
+
+/// Files made for this run that are only worth keeping if it succeeds, such as a script staged
+/// from stdin: [`cli_main`] deletes them when `run` fails or panics. A successful run keeps them
+/// for the next one to reuse, and an exec'd script may still need them. Anything a killed
+/// process leaves behind is for `scriptr cache prune` to sweep up.
+static DISPOSABLE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Have `path` removed if this run fails; see [`DISPOSABLE`].
+fn dispose_on_failure(path: &Path) {
+    DISPOSABLE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(path.to_path_buf());
+}
+
+/// Remove whatever [`dispose_on_failure`] was given, as best it can.
+fn remove_disposable() {
+    // Not `lock`: a panic while it's held would otherwise deadlock the panic hook.
+    let mut paths = match DISPOSABLE.try_lock() {
+        Ok(paths) => paths,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => return,
+    };
+    for path in paths.drain(..) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Exit code for scriptr's own failures, as opposed to cargo's (`EX_SOFTWARE` from sysexits.h).
+const EXIT_INTERNAL: u8 = 70;
+
+/// Builds and runs scripts from other programs, sharing the command line's cache. Anything not
+/// configured is as the command line's default, including what `SCRIPTR_*` environment
+/// variables set.
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// let dir = tempfile::tempdir()?;
+/// let script = dir.path().join("hello.rs");
+/// std::fs::write(&script, "fn main() { println!(\"hello\"); }\n")?;
+///
+/// let launcher = scriptr::Launcher::builder()
+///     .cache_dir(dir.path().join("cache"))
+///     .quiet(true)
+///     .build();
+/// let bin = launcher.prepare(&script)?;
+/// assert!(bin.is_file());
+/// // The second time round it comes straight from the cache.
+/// assert_eq!(launcher.prepare(&script)?, bin);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Launcher {
+    cache_dir: Option<PathBuf>,
+    profile: Option<String>,
+    toolchain: Option<String>,
+    cargo_args: Vec<String>,
+    quiet: bool,
+}
+
+/// Configures a [`Launcher`]; see [`Launcher::builder`].
+#[derive(Clone, Debug, Default)]
+pub struct LauncherBuilder {
+    launcher: Launcher,
+}
+
+impl LauncherBuilder {
+    /// Where binaries and metadata are cached; the platform cache dir's `scriptr/` by default.
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.launcher.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// The cargo profile to build with, as for `--profile`; `release` by default.
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.launcher.profile = Some(name.into());
+        self
+    }
+
+    /// The toolchain to build with, as for `--toolchain`; otherwise the script's
+    /// `rust-toolchain.toml` or nightly.
+    pub fn toolchain(mut self, name: impl Into<String>) -> Self {
+        self.launcher.toolchain = Some(name.into());
+        self
+    }
+
+    /// Extra `cargo build` arguments, as for `--cargo-arg`.
+    pub fn cargo_args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.launcher.cargo_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Keep scriptr's own status lines and cargo's warnings off stderr, as for `--quiet`.
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.launcher.quiet = quiet;
+        self
+    }
+
+    pub fn build(self) -> Launcher {
+        self.launcher
+    }
+}
+
+impl Launcher {
+    pub fn builder() -> LauncherBuilder {
+        LauncherBuilder::default()
+    }
+
+    /// Return the path of `script`'s binary, building it first unless the cached one is current.
+    pub fn prepare(&self, script: &Path) -> Result<PathBuf> {
+        Ok(self.job(script)?.prepare(false, false)?.bin)
+    }
+
+    /// Build `script` if need be, then run it with `args` and wait for it to finish.
+    pub fn run<I, S>(&self, script: &Path, args: I) -> Result<ExitStatus>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let bin = self.prepare(script)?;
+        let args: Vec<OsString> = args.into_iter().map(|a| a.as_ref().to_owned()).collect();
+        let env = ScriptEnv::default();
+        script_command(&bin, &script_arg0(script), &args, &env, Stdio::inherit())
+            .status()
+            .with_context(|| format!("failed to run {}", bin.display()))
+    }
+
+    fn job(&self, script: &Path) -> Result<Job> {
+        // Whatever isn't set here is as for `scriptr <script>`, environment variables included.
+        let mut opts = Opts::try_parse_from([NAME, "-"]).context("invalid scriptr defaults")?;
+        if let Some(name) = &self.profile {
+            opts.profile = Some(parse_profile(name).map_err(anyhow::Error::msg)?);
+        }
+        if self.toolchain.is_some() {
+            opts.toolchain.clone_from(&self.toolchain);
+        }
+        opts.cargo_arg.clone_from(&self.cargo_args);
+        opts.quiet |= self.quiet;
+        let options = JobOptions::from_opts(&opts)?;
+
+        let cache_root = cache_root(self.cache_dir.clone().or(opts.cache_dir))?;
+        let script = resolve_script(script, !opts.no_canonicalize)?;
+        let settings = options.settings(&script, &cache_root)?;
+        let meta_path = options.meta_path(&script, &cache_root, &settings.toolchain)?;
+        let manifest = settings.manifest(&cache_root, &script)?;
+        Ok(Job::new(
+            script, manifest, cache_root, meta_path, settings, &options,
+        ))
+    }
+}
+
+/// Replace the current process with `bin`, as the command line does once a script is built.
+///
+/// Should that fail, the reason is printed and the process exits with status 70
+/// (`EX_SOFTWARE`). On Windows, `bin` runs as a child and its exit code becomes ours.
+pub fn exec<I, S>(bin: &Path, args: I) -> !
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<OsString> = args.into_iter().map(|a| a.as_ref().to_owned()).collect();
+    let err = exec_image(
+        bin,
+        bin.as_os_str(),
+        &args,
+        &ScriptEnv::default(),
+        Stdio::inherit(),
+    );
+    eprintln!("Error: failed to run {}: {err}", bin.display());
+    std::process::exit(EXIT_INTERNAL.into())
+}
+
+/// The `scriptr` command line; `src/main.rs` only calls this.
+#[doc(hidden)]
+pub fn cli_main() -> ExitCode {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        remove_disposable();
+        default_hook(info);
+    }));
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            remove_disposable();
+            if log_format() == LogFormat::Json {
+                log(Level::Error, None, format_args!("{err:?}"));
+            } else if color() {
+                eprintln!("\x1b[1;31mError:\x1b[0m {err:?}");
+            } else {
+                eprintln!("Error: {err:?}");
+            }
+            // A failed cargo build exits with cargo's own code, so callers can tell them apart.
+            let code = err
+                .downcast_ref::<CargoFailed>()
+                .and_then(|failed| failed.status.code())
+                .or_else(|| err.downcast_ref::<CachedFailure>()?.code)
+                .and_then(|code| u8::try_from(code).ok())
+                .unwrap_or(EXIT_INTERNAL);
+            ExitCode::from(code)
+        }
+    }
+}
+
+fn run() -> Result<()> {
+    let started = Instant::now();
+    // Manual argument parsing to prevent script args from being interpreted as scriptr options
+    let mut all_args = expand_shebang_options(std::env::args_os().collect());
+    // Like `cache`, a script literally named `completions` has to be run as `./completions`.
+    if all_args.get(1).is_some_and(|arg| arg == "completions") {
+        let opts = CompletionsOpts::parse_from(&all_args[1..]);
+        clap_complete::generate(
+            opts.shell,
+            &mut Opts::command(),
+            "scriptr",
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
+    if all_args
+        .get(1)
+        .is_some_and(|arg| arg == "cache" || arg == "gc")
+    {
+        // `scriptr gc ...` is short for `scriptr cache gc ...`.
+        let cache_args = if all_args[1] == "gc" {
+            &all_args[..]
+        } else {
+            &all_args[1..]
+        };
+        let opts = CacheOpts::parse_from(cache_args);
+        let config_dir = load_config()?.and_then(|(_, config)| config.cache_dir);
+        return cache_command(opts.command, opts.cache_dir.or(config_dir));
+    }
+    // `scriptr run foo.rs` is the same as `scriptr foo.rs`; `scriptr build foo.rs` stops short of
+    // running it. Both are dropped before the rest is split, so everything else applies as usual.
+    let build_only = all_args.get(1).is_some_and(|arg| arg == "build");
+    if build_only || all_args.get(1).is_some_and(|arg| arg == "run") {
+        all_args.remove(1);
+    }
+    let (script_index, mut passthrough_args) = split_invocation_args(&all_args);
+
+    // Split args at the script boundary
+    let scriptr_args = if let Some(idx) = script_index {
+        // Give clap everything up to and including the script path
+        all_args[..=idx].to_vec()
+    } else {
+        // No script found - let clap handle it (probably --help or error)
+        all_args
+    };
+
+    // Parse only scriptr's portion, then fill in anything left unset from the script's companion
+    // file, then from the config file
+    let config = load_config()?;
+    let opts = Opts::parse_from(scriptr_args);
+    let companion = match opts.script.as_deref() {
+        Some(script) if opts.eval.is_none() && script.as_os_str() != "-" => {
+            load_companion(script, !opts.no_canonicalize)?
+        }
+        _ => None,
+    };
+    let (companion_path, opts) = match companion {
+        Some((path, companion)) => (Some(path), companion.apply(opts)?),
+        None => (None, opts),
+    };
+    let (config_path, opts) = match config {
+        Some((path, config)) => (Some(path), config.apply(opts)),
+        None => (None, opts),
+    };
+    let mut options = JobOptions::from_opts(&opts)?;
+    let report = options.report;
+    let Opts {
+        debug: _,
+        default_profile: _,
+        small: _,
+        profile: _,
+        verbose: _,
+        quiet,
+        force,
+        clean,
+        clean_only,
+        strict_clean,
+        update,
+        hash_only: _,
+        trust_mtime: _,
+        print_path,
+        install,
+        check,
+        clippy,
+        explain,
+        dry_run,
+        verify,
+        dump_manifest,
+        print_cache_key,
+        build_all,
+        watch,
+        watch_interval,
+        time_limit,
+        since,
+        status_file,
+        color: color_choice,
+        log_format,
+        log_file,
+        json,
+        show_warnings: _,
+        toolchain,
+        locked: _,
+        offline: _,
+        frozen: _,
+        isolated,
+        jobs: _,
+        features: _,
+        all_features: _,
+        no_default_features: _,
+        rustflags: _,
+        cargo_arg: _,
+        cache_dir,
+        cargo,
+        build_retries: _,
+        cache_failures: _,
+        no_cache,
+        dedup: _,
+        shared_target: _,
+        raw_bin_path: _,
+        no_canonicalize,
+        max_cache_size: _,
+        rebuild_if_older_than: _,
+        hash: _,
+        exec_arg0,
+        env,
+        run_in,
+        args_file,
+        target,
+        edition: _,
+        id,
+        content_keyed,
+        stdin_name,
+        eval,
+        wrap,
+        version,
+        script,
+    } = opts;
+
+    if let Some(path) = args_file {
+        passthrough_args.extend(read_args_file(&path)?);
+    }
+    let run_in = match run_in {
+        Some(dir) if !dir.is_dir() => {
+            anyhow::bail!("--run-in {}: no such directory", dir.display())
+        }
+        // Made absolute now, while it still means what the caller meant by it.
+        dir => dir.map(std::path::absolute).transpose()?,
+    };
+
+    let verbose = report.verbose;
+    if let Some(path) = &log_file {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("cannot open log file {}", path.display()))?;
+        LOG_FILE.set(Mutex::new(file)).ok();
+    }
+    COLOR
+        .set(log_file.is_none() && color_choice.enabled(json))
+        .ok();
+    LOG_FORMAT.set(log_format).ok();
+    if let Some(cargo) = cargo {
+        CARGO.set(check_cargo_program(cargo)?).ok();
+    }
+
+    if verbose && let Some(path) = config_path {
+        debug!("Loaded config: {}", path.display());
+    }
+    if verbose && let Some(path) = companion_path {
+        debug!("Loaded companion file: {}", path.display());
+    }
+
+    if version {
+        println!("{NAME} {}", env!("CARGO_PKG_VERSION"));
+        if verbose {
+            let near = match &script {
+                Some(script) => resolve_script(script, !no_canonicalize)?,
+                None => std::env::current_dir()?.join("-"),
+            };
+            for (name, value) in environment_report(toolchain, &near, cache_dir)? {
+                println!("{name}: {value}");
+            }
+        }
+        return Ok(());
+    }
+    // An --eval snippet has no path until it's staged below, like a script from stdin.
+    let script = script
+        .or_else(|| eval.is_some().then(PathBuf::new))
+        .expect("clap requires a script unless --version or --eval is given");
+
+    // -------------- cache bookkeeping ---------------------------------------
+    // With --no-cache, a fresh scratch dir stands in for the cache root: stdin staging and the
+    // cargo target dir go there, and no metadata is ever written to it.
+    let cache_root = if no_cache {
+        scratch_dir()?
+    } else {
+        cache_root(cache_dir)?
+    };
+
+    // -------------- --build-all: warm the cache for many scripts ---------------
+    // Everything after the first script is another script rather than arguments to it.
+    if build_all {
+        let mut scripts = vec![script];
+        scripts.extend(passthrough_args.into_iter().map(PathBuf::from));
+        let job_for = |script: &Path| -> Result<Job> {
+            let resolved = resolve_script(script, !no_canonicalize)?;
+            let settings = options.settings(&resolved, &cache_root)?;
+            let meta_path = options.meta_path(&resolved, &cache_root, &settings.toolchain)?;
+            let manifest = settings.manifest(&cache_root, &resolved)?;
+            if clean && let Err(err) = clean_entry(&meta_path) {
+                if strict_clean {
+                    return Err(err);
+                }
+                if !quiet {
+                    note!("Warning: {err:#}; rebuilding regardless");
+                }
+            }
+            Ok(Job::new(
+                resolved,
+                manifest,
+                cache_root.clone(),
+                meta_path,
+                settings,
+                &options,
+            ))
+        };
+        let jobs = scripts
+            .into_iter()
+            .map(|script| {
+                let job = job_for(&script);
+                (script, job)
+            })
+            .collect();
+        // Whatever --clean couldn't remove mustn't be used, and the rest is gone anyway.
+        return build_each(jobs, force || clean, update);
+    }
+
+    // `-` means the script body arrives on stdin; stage it to a real file cargo can build.
+    let from_stdin = script.as_os_str() == "-";
+    // Named as invoked, before symlinks are resolved, so that multi-call scripts reached through
+    // links of different names can tell which one it was.
+    let arg0 = exec_arg0.unwrap_or_else(|| match (&eval, from_stdin) {
+        (Some(_), _) => "eval".into(),
+        (None, true) => "stdin".into(),
+        (None, false) => script_arg0(&script),
+    });
+    if stdin_name.is_some() && !from_stdin {
+        anyhow::bail!("--stdin-name only applies to a script read from stdin (`-`)");
+    }
+    let (script, staged) = if let Some(code) = &eval {
+        let src = if wrap && !code.contains("fn main") {
+            format!("fn main() {{\n{code}\n}}\n")
+        } else {
+            code.clone()
+        };
+        let (path, hash) = stage_script(&cache_root, "eval", src.as_bytes())?;
+        dispose_on_failure(&path);
+        (path, Some(("eval", hash)))
+    } else if from_stdin {
+        let mut src = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut src)
+            .context("failed to read script from stdin")?;
+        let (path, hash) = stage_script(&cache_root, "stdin", &src)?;
+        dispose_on_failure(&path);
+        (path, Some(("stdin", hash)))
+    } else {
+        (resolve_script(&script, !no_canonicalize)?, None)
+    };
+    // A staged script is rewritten on every run, so its mtime is meaningless.
+    options.hash_only |= staged.is_some();
+    // A staged script's name already says what's in it; its mtime says nothing.
+    options.trust_mtime &= !options.hash_only;
+    if dump_manifest {
+        return dump_embedded_manifest(&script);
+    }
+    let settings = options.settings(&script, &cache_root)?;
+    let manifest = settings.manifest(&cache_root, &script)?;
+    if verbose && !isolated && settings.isolated_dir.is_some() {
+        debug!("Script is beside a Cargo.toml; building it in isolation");
+    }
+
+    if verbose {
+        debug!("Script: {}", script.display());
+        debug!("Toolchain: {}", settings.toolchain);
+    }
+
+    if check || clippy {
+        // Diagnostics only: nothing is read from or written to the cache.
+        settings.toolchain.preflight(verbose)?;
+        let (command, cmd) = if clippy {
+            settings.toolchain.preflight_clippy()?;
+            ("clippy", settings.clippy_command(&manifest, verbose))
+        } else {
+            ("check", settings.check_command(&manifest, verbose))
+        };
+        let phase = format!("cargo {command}");
+        let checked = timed(verbose, &phase, || run_cargo(cmd, command, report, 0, None))?;
+        for diagnostic in &checked.diagnostics {
+            eprint!("{diagnostic}");
+        }
+        return Ok(());
+    }
+
+    // Key metadata by explicit ID, by stdin name or content, by the script's content, or by
+    // absolute path.
+    let meta_path = if let Some(ref id) = id {
+        meta_path_for(&cache_root, "id", id.as_bytes(), &settings.toolchain)
+    } else if let Some(ref name) = stdin_name {
+        meta_path_for(
+            &cache_root,
+            "stdin-name",
+            name.as_bytes(),
+            &settings.toolchain,
+        )
+    } else if let Some((kind, ref hash)) = staged {
+        meta_path_for(&cache_root, kind, hash.as_bytes(), &settings.toolchain)
+    } else if content_keyed {
+        timed(verbose, "hash", || {
+            options.meta_path(&script, &cache_root, &settings.toolchain)
+        })?
+    } else {
+        options.meta_path(&script, &cache_root, &settings.toolchain)?
+    };
+
+    if verbose {
+        if let Some(ref id) = id {
+            debug!("Cache key source: id={id}");
+        } else if let Some(ref name) = stdin_name {
+            debug!("Cache key source: stdin name={name}");
+        } else if let Some((kind, _)) = staged {
+            debug!("Cache key source: {kind} content");
+        } else if content_keyed {
+            debug!("Cache key source: content");
+        } else {
+            debug!("Cache key source: path");
+        }
+        debug!("Cache path: {}", meta_path.display());
+    }
+    if print_cache_key {
+        let key = meta_path.file_stem().unwrap_or_default();
+        println!("key: {}", key.to_string_lossy());
+        println!("metadata: {}", meta_path.display());
+        println!("variant: {}", settings.variant());
+        return Ok(());
+    }
+
+    // -------------- handle clean flags --------------------------------------
+    let mut force = force;
+    if clean || clean_only {
+        match clean_entry(&meta_path) {
+            Ok(true) if verbose => debug!("Removed cache: {}", meta_path.display()),
+            Ok(false) if verbose => debug!("No cache to clean"),
+            Ok(_) => {}
+            Err(err) if strict_clean || clean_only => return Err(err),
+            Err(err) => {
+                if !quiet {
+                    note!("Warning: {err:#}; rebuilding regardless");
+                }
+                // What's left of the entry is stale by the user's own account.
+                force = true;
+            }
+        }
+
+        if clean_only {
+            if staged.is_some() {
+                fs::remove_file(&script)?;
+            }
+            if verbose {
+                debug!("Clean complete, exiting");
+            }
+            return Ok(());
+        }
+    }
+
+    let job = Job::new(script, manifest, cache_root, meta_path, settings, &options);
+    // A cross-compiled binary can't be exec'd here, so it's handed back like --print-path.
+    let foreign = target.as_deref().filter(|triple| !runs_on_host(triple));
+    if build_only {
+        if watch {
+            anyhow::bail!("`scriptr build` doesn't run the script, so there's nothing to --watch");
+        }
+        if !passthrough_args.is_empty() {
+            anyhow::bail!(
+                "`scriptr build` doesn't run the script, so it takes no arguments for it"
+            );
+        }
+    }
+    if let Some(triple) = foreign {
+        if watch {
+            anyhow::bail!("--watch can't run a binary built for {triple}");
+        }
+        if verbose && !print_path {
+            debug!("{triple} binaries don't run on this host; printing the path instead");
+        }
+    }
+    let print_path = print_path || foreign.is_some();
+    let force = force
+        || since
+            .as_deref()
+            .is_some_and(|rev| changed_since(&job.script, rev, report) == Some(true));
+
+    if explain {
+        for line in job.explain(force, update)? {
+            println!("{line}");
+        }
+        return Ok(());
+    }
+    if dry_run {
+        for line in job.dry_run(force, update)? {
+            println!("{line}");
+        }
+        return Ok(());
+    }
+    if verify {
+        let verification = job.verify()?;
+        for line in verification.lines() {
+            println!("{line}");
+        }
+        if !verification.identical() {
+            anyhow::bail!("the cached binary doesn't match a fresh build");
+        }
+        return Ok(());
+    }
+    let env = ScriptEnv {
+        vars: env,
+        dir: run_in,
+    };
+    if watch {
+        if from_stdin {
+            anyhow::bail!("--watch needs a script file, not stdin");
+        }
+        let interval = Duration::from_millis(watch_interval);
+        return watch_loop(
+            &job,
+            &arg0,
+            &passthrough_args,
+            &env,
+            force,
+            update,
+            interval,
+        );
+    }
+
+    let write_status = |bin: &Path, cache_hit: bool, since: Instant| -> Result<()> {
+        let Some(path) = &status_file else {
+            return Ok(());
+        };
+        let status = serde_json::json!({
+            "cache": if cache_hit { "hit" } else { "miss" },
+            "rebuilt": !cache_hit,
+            "bin": bin,
+            "build_ms": since.elapsed().as_millis() as u64,
+        });
+        fs::write(path, format!("{status}\n"))
+            .with_context(|| format!("cannot write status file {}", path.display()))
+    };
+
+    let preparing = Instant::now();
+    let Prepared {
+        bin: bin_path,
+        cache_hit,
+    } = job.prepare(force, update).map_err(|err| match &target {
+        Some(triple) if err.downcast_ref::<CargoFailed>().is_some() => err.context(format!(
+            "build for target {triple} failed — if its toolchain isn't installed, run `rustup target add {triple}`"
+        )),
+        _ => err,
+    })?;
+    write_status(&bin_path, cache_hit, preparing)?;
+    if json {
+        let reason = if cache_hit {
+            "scriptr-cache-hit"
+        } else {
+            "scriptr-ran"
+        };
+        println!(
+            "{}",
+            serde_json::json!({ "reason": reason, "bin": bin_path })
+        );
+    }
+    if build_only && !print_path {
+        if verbose {
+            debug!("Built {}, not running it", bin_path.display());
+        }
+        return Ok(());
+    }
+    if let Some(dir) = install {
+        if from_stdin {
+            anyhow::bail!("--install needs a script file, not stdin");
+        }
+        println!(
+            "{}",
+            install_to(&bin_path, &dir, &job.script, force)?.display()
+        );
+        return Ok(());
+    }
+    if verbose && !print_path {
+        log(
+            Level::Debug,
+            Some("startup to exec"),
+            format_args!(
+                "phase startup to exec took {:.2}ms",
+                started.elapsed().as_secs_f64() * 1e3
+            ),
+        );
+        debug!("Executing: {}", bin_path.display());
+    }
+    let time_limit = time_limit.map(Duration::from_secs);
+    match launch(
+        &bin_path,
+        &arg0,
+        &passthrough_args,
+        &env,
+        print_path,
+        from_stdin,
+        time_limit,
+    ) {
+        // The cached binary vanished after the fast path checked it (say, a cache cleaner ran in
+        // between). Rather than fail a run that would otherwise have worked, build it again.
+        Err(err) if cache_hit && is_not_found(&err) => {
+            if verbose {
+                debug!("Cached binary is missing ({err:#}), rebuilding");
+            }
+            // A file exec can't find its way into (e.g. a broken interpreter line) would survive
+            // a rebuild: cargo only restores outputs that are missing.
+            let _ = fs::remove_file(&bin_path);
+            let preparing = Instant::now();
+            let Prepared { bin, .. } = job.prepare(true, false)?;
+            write_status(&bin, false, preparing)?;
+            launch(
+                &bin,
+                &arg0,
+                &passthrough_args,
+                &env,
+                print_path,
+                from_stdin,
+                time_limit,
+            )
+        }
+        result => result,
+    }
+}
+
+/// Whether `script` differs from its version at git revision `rev` (`--since`), as `git diff
+/// --quiet` sees it. `None`, after a warning, when git can't tell: the script isn't in a
+/// repository, the revision is unknown, or there's no git. A file git doesn't track never differs.
+fn changed_since(script: &Path, rev: &str, report: Reporting) -> Option<bool> {
+    let dir = script.parent().unwrap_or(Path::new("."));
+    let git = |args: &[&OsStr]| {
+        Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|err| format!("cannot run git: {err}"))
+    };
+    let stderr = |out: Output| String::from_utf8_lossy(&out.stderr).trim().to_string();
+    // Outside a repository `git diff` quietly turns into `git diff --no-index`, so resolve the
+    // revision first.
+    let commit = format!("{rev}^{{commit}}");
+    let changed = git(&["rev-parse", "--verify", &commit].map(OsStr::new)).and_then(|out| {
+        if !out.status.success() {
+            return Err(stderr(out));
+        }
+        let out = git(&[
+            OsStr::new("diff"),
+            OsStr::new("--quiet"),
+            OsStr::new(rev),
+            OsStr::new("--"),
+            script.as_os_str(),
+        ])?;
+        match out.status.code() {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            _ => Err(stderr(out)),
+        }
+    });
+    match changed {
+        Ok(changed) => {
+            if changed && report.verbose {
+                debug!("{} changed since {rev}; rebuilding", script.display());
+            }
+            Some(changed)
+        }
+        Err(problem) => {
+            if !report.quiet {
+                note!(
+                    "Warning: --since {rev} can't compare {} with git ({problem}); using the \
+                     usual cache checks",
+                    script.display()
+                );
+            }
+            None
+        }
+    }
+}
+
+fn is_not_found(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|io| io.kind() == std::io::ErrorKind::NotFound)
+}
+
+/// A script invocation resolved to its cache entry and build settings.
+struct Job {
+    script: PathBuf,
+    /// What cargo is told the script is; see [`manifest_path`].
+    manifest: PathBuf,
+    cache_root: PathBuf,
+    meta_path: PathBuf,
+    settings: BuildSettings,
+    hash_only: bool,
+    /// A changed mtime is a miss outright, with no hash to show the contents are the same
+    /// (`--trust-mtime`).
+    trust_mtime: bool,
+    hash_algo: HashAlgo,
+    /// How often a build that failed transiently is retried.
+    build_retries: u32,
+    /// Record failed builds, and fail straight away while the sources stay the same.
+    cache_failures: bool,
+    /// Store binaries by content, so identical scripts at different paths share one build.
+    dedup: bool,
+    /// Memoized [`Job::builder_version`].
+    builder_version: OnceLock<Option<String>>,
+    /// Skip the metadata entirely and build into `cache_root/target`.
+    no_cache: bool,
+    shared_target: bool,
+    /// Keep `meta.bin` pointing into cargo's target dir rather than at a copy under `bin/`
+    /// (`--raw-bin-path`); ignored with `shared_target` and `dedup`, which need the copy.
+    raw_bin_path: bool,
+    max_cache_size: Option<u64>,
+    /// Treat binaries built longer ago than this as stale (`--rebuild-if-older-than`).
+    max_age: Option<Duration>,
+    report: Reporting,
+}
+
+/// How much scriptr says about what it's doing, and in what form.
+#[derive(Clone, Copy)]
+struct Reporting {
+    verbose: bool,
+    /// Only build errors get through: no `[scriptr]` lines and no cargo warnings.
+    quiet: bool,
+    /// Cargo's messages are forwarded to stdout as JSON.
+    json: bool,
+    /// A successful build's warnings are printed too (`--show-warnings`).
+    show_warnings: bool,
+    /// A [`Progress`] line shows while a build runs: stderr is a terminal that nothing else is
+    /// being written to.
+    progress: bool,
+}
+
+/// How an invocation builds and caches scripts, whichever it resolves: the command line's
+/// options, or a [`Launcher`]'s, which start from the command line's defaults.
+struct JobOptions {
+    /// `--toolchain`; otherwise each script's `rust-toolchain.toml`, or nightly.
+    toolchain: Option<String>,
+    profile: Profile,
+    cargo_args: Vec<String>,
+    target: Option<String>,
+    rustflags: String,
+    locked: bool,
+    offline: bool,
+    frozen: bool,
+    isolated: bool,
+    jobs: Option<u32>,
+    features: Features,
+    registry: String,
+    edition: Option<String>,
+    /// Key entries by the script's content rather than its path (`--content-keyed`).
+    content_keyed: bool,
+    hash_only: bool,
+    trust_mtime: bool,
+    hash_algo: HashAlgo,
+    build_retries: u32,
+    cache_failures: bool,
+    dedup: bool,
+    no_cache: bool,
+    shared_target: bool,
+    raw_bin_path: bool,
+    max_cache_size: Option<u64>,
+    max_age: Option<Duration>,
+    report: Reporting,
+}
+
+impl JobOptions {
+    fn from_opts(opts: &Opts) -> Result<Self> {
+        let profile = if let Some(profile) = &opts.profile {
+            profile.clone()
+        } else if opts.small {
+            Profile::Small
+        } else if opts.debug || opts.default_profile == Some(DefaultProfile::Debug) {
+            Profile::Debug
+        } else {
+            Profile::Release
+        };
+        let quiet = opts.quiet;
+        let json = opts.json;
+        let no_log_file = opts.log_file.is_none();
+        // Content keying hashes the script just to find its entry, which leaves an mtime
+        // nothing to vouch for.
+        let hash_only = opts.hash_only || opts.content_keyed;
+        Ok(Self {
+            toolchain: opts.toolchain.clone(),
+            profile,
+            cargo_args: opts.cargo_arg.clone(),
+            target: opts.target.clone(),
+            rustflags: effective_rustflags(std::env::var("RUSTFLAGS").ok(), opts.rustflags.clone()),
+            locked: opts.locked,
+            offline: opts.offline,
+            frozen: opts.frozen,
+            isolated: opts.isolated,
+            jobs: opts.jobs,
+            features: Features::new(&opts.features, opts.all_features, opts.no_default_features)?,
+            registry: registry_id(),
+            edition: opts.edition.clone(),
+            content_keyed: opts.content_keyed,
+            hash_only,
+            trust_mtime: opts.trust_mtime && !hash_only,
+            hash_algo: opts.hash,
+            build_retries: opts.build_retries,
+            cache_failures: opts.cache_failures,
+            dedup: opts.dedup && !opts.no_cache,
+            no_cache: opts.no_cache,
+            shared_target: opts.shared_target && !opts.no_cache,
+            raw_bin_path: opts.raw_bin_path,
+            max_cache_size: opts.max_cache_size,
+            max_age: opts.rebuild_if_older_than,
+            report: Reporting {
+                verbose: opts.verbose || !no_log_file,
+                quiet,
+                json,
+                show_warnings: opts.show_warnings,
+                progress: !quiet && !json && no_log_file && std::io::stderr().is_terminal(),
+            },
+        })
+    }
+
+    /// The settings to build `script` with, cached under `cache_root`. Its lockfile is left for
+    /// [`Job::new`], once the entry is known.
+    fn settings(&self, script: &Path, cache_root: &Path) -> Result<BuildSettings> {
+        Ok(BuildSettings {
+            toolchain: Toolchain::resolve(self.toolchain.clone(), script)?,
+            profile: self.profile.clone(),
+            cargo_args: self.cargo_args.clone(),
+            target: self.target.clone(),
+            rustflags: self.rustflags.clone(),
+            lockfile: None,
+            locked: self.locked,
+            offline: self.offline,
+            frozen: self.frozen,
+            isolated_dir: isolated_dir(self.isolated, script, cache_root),
+            jobs: self.jobs,
+            features: self.features.clone(),
+            registry: self.registry.clone(),
+            edition: self.edition.clone(),
+        })
+    }
+
+    /// Where `script`'s metadata lives when nothing more specific names its entry: under a
+    /// digest of its content or of its path.
+    fn meta_path(
+        &self,
+        script: &Path,
+        cache_root: &Path,
+        toolchain: &Toolchain,
+    ) -> Result<PathBuf> {
+        Ok(if self.content_keyed {
+            let content = fingerprint_sources(script, self.hash_algo)?.hash;
+            meta_path_for(cache_root, "content", content.as_bytes(), toolchain)
+        } else {
+            let path = script.as_os_str().as_encoded_bytes();
+            meta_path_for(cache_root, "path", path, toolchain)
+        })
+    }
+}
+
+/// The fast path's verdict on a cache entry, with the evidence for it.
+struct Lookup {
+    /// The cached binary, when it can be used as is.
+    bin: Option<PathBuf>,
+    /// One line, e.g. "cache hit: mtime matches".
+    verdict: String,
+    /// The cached and current values the verdict rests on.
+    details: Vec<String>,
+    /// Why the miss is worth mentioning even without `--verbose`.
+    warning: Option<String>,
+}
+
+/// What `--verify` found: BLAKE3 digests of the cached binary as it is now, as recorded when it
+/// was built (if it was), and of a fresh build.
+struct Verification {
+    bin: PathBuf,
+    cached: String,
+    recorded: Option<String>,
+    fresh: String,
+}
+
+impl Verification {
+    fn identical(&self) -> bool {
+        self.cached == self.fresh
+    }
+
+    /// The report to print, one line each.
+    fn lines(&self) -> Vec<String> {
+        if self.identical() {
+            return vec![format!("verified: identical (blake3 {})", self.fresh)];
+        }
+        let mut lines = vec![
+            format!("MISMATCH: {}", self.bin.display()),
+            format!("  cached:      {}", self.cached),
+            format!("  fresh build: {}", self.fresh),
+        ];
+        if let Some(recorded) = self.recorded.as_ref().filter(|&r| *r != self.cached) {
+            lines.push(format!(
+                "  recorded:    {recorded} (the cached binary changed after it was built)"
+            ));
+        }
+        lines
+    }
+}
+
+/// A binary ready to run, and whether it came straight from the cache.
+struct Prepared {
+    bin: PathBuf,
+    cache_hit: bool,
+}
+
+impl Job {
+    /// A job for `script`, built through `manifest` (see [`BuildSettings::manifest`]) with its
+    /// metadata at `meta_path`.
+    fn new(
+        script: PathBuf,
+        manifest: PathBuf,
+        cache_root: PathBuf,
+        meta_path: PathBuf,
+        mut settings: BuildSettings,
+        options: &JobOptions,
+    ) -> Self {
+        settings.lockfile = (!is_cargo_manifest(&script)).then(|| lockfile_path(&meta_path));
+        Self {
+            script,
+            manifest,
+            cache_root,
+            meta_path,
+            settings,
+            hash_only: options.hash_only,
+            trust_mtime: options.trust_mtime,
+            hash_algo: options.hash_algo,
+            build_retries: options.build_retries,
+            cache_failures: options.cache_failures,
+            dedup: options.dedup,
+            builder_version: OnceLock::new(),
+            no_cache: options.no_cache,
+            shared_target: options.shared_target,
+            raw_bin_path: options.raw_bin_path,
+            max_cache_size: options.max_cache_size,
+            max_age: options.max_age,
+            report: options.report,
+        }
+    }
+
+    /// Return the script's binary: the cached one if still valid, otherwise freshly built.
+    fn prepare(&self, force: bool, update: bool) -> Result<Prepared> {
+        // -------------- fast‑path check -------------------------------------
+        let skip_cache = force || update || self.no_cache;
+        if skip_cache {
+            if self.report.verbose {
+                if self.no_cache {
+                    debug!("--no-cache, building fresh");
+                } else if update {
+                    debug!("Update requested, skipping cache");
+                } else {
+                    debug!("Force rebuild requested");
+                }
+            }
+        } else if let Some(bin) = timed(self.report.verbose, "cache lookup", || self.cached())? {
+            return Ok(Prepared {
+                bin,
+                cache_hit: true,
+            });
+        } else if self.cache_failures
+            && let Some(failure) = self.cached_failure()?
+        {
+            if !self.report.quiet {
+                note!("Sources unchanged since the last failed build; repeating its errors");
+            }
+            eprint!("{}", failure.output);
+            return Err(CachedFailure { code: failure.code }.into());
+        }
+
+        // -------------- reuse a binary built from the same sources elsewhere -
+        if self.dedup && !skip_cache {
+            let fp = self
+                .settings
+                .fingerprint(fingerprint_sources(&self.script, self.hash_algo)?);
+            // The shared binary was built when it was last written, not now.
+            let deduped = self.deduped_binary(&fp).and_then(|bin| {
+                let built_at = fs::metadata(&bin).and_then(|m| m.modified()).ok();
+                let built_at = built_at.map(unix_secs);
+                self.stale(built_at).is_none().then_some((bin, built_at))
+            });
+            if let Some((bin, built_at)) = deduped {
+                if self.report.verbose {
+                    debug!(
+                        "Reusing binary built from identical sources: {}",
+                        bin.display()
+                    );
+                }
+                let source_bytes = source_bytes(&self.script, &fp.modules);
+                self.record_variant(Variant {
+                    fp,
+                    bin: bin.clone(),
+                    builder_version: self.builder_version().map(str::to_string),
+                    built_at,
+                    bin_hash: hash_file(&bin).ok(),
+                    source_bytes: Some(source_bytes),
+                    build_ms: None,
+                    bin_bytes: fs::metadata(&bin).map(|meta| meta.len()).ok(),
+                })?;
+                return Ok(Prepared {
+                    bin,
+                    cache_hit: true,
+                });
+            }
+        }
+
+        // -------------- serialize builds of this script ---------------------
+        // Two concurrent misses would otherwise both run cargo on the same target dir. Held until
+        // the metadata is written; dropping the file releases it.
+        let lock = File::create(self.meta_path.with_extension("lock"))?;
+        if lock.try_lock_exclusive().is_err() {
+            if self.report.verbose {
+                debug!("Waiting for another build of this script...");
+            }
+            lock.lock_exclusive()?;
+            // Whoever held the lock has probably just built exactly what we need.
+            if !skip_cache
+                && let Some(bin) = timed(self.report.verbose, "cache lookup", || self.cached())?
+            {
+                return Ok(Prepared {
+                    bin,
+                    cache_hit: true,
+                });
+            }
+        }
+
+        // Only now that cargo is definitely needed is it worth checking for it.
+        self.settings.toolchain.preflight(self.report.verbose)?;
+
+        // -------------- update deps if requested -----------------------------
+        if update {
+            if self.report.verbose {
+                debug!("Updating dependencies...");
+            }
+            update_deps(&self.manifest, &self.settings, self.report.verbose)?;
+        }
+
+        // -------------- rebuild ---------------------------------------------
+        // Fingerprinted before cargo reads the sources, so the record can't describe an edit
+        // made during the build that the binary doesn't have.
+        let sources = fingerprint_sources(&self.script, self.hash_algo)?;
+        if self.report.verbose {
+            debug!("Building script...");
+        }
+        let building = Instant::now();
+        let bin_path = if self.no_cache {
+            if self.report.verbose {
+                debug!(
+                    "Scratch target: {}",
+                    self.cache_root.join("target").display()
+                );
+            }
+            let cmd = self.cargo_build_command();
+            return Ok(Prepared {
+                bin: timed(self.report.verbose, "cargo build", || {
+                    rebuild(
+                        cmd,
+                        &self.script,
+                        &self.settings.toolchain,
+                        self.report,
+                        self.build_retries,
+                    )
+                })?,
+                cache_hit: false,
+            });
+        } else if self.shared_target {
+            self.rebuild_shared()
+        } else {
+            let cmd = self.cargo_build_command();
+            timed(self.report.verbose, "cargo build", || {
+                rebuild(
+                    cmd,
+                    &self.script,
+                    &self.settings.toolchain,
+                    self.report,
+                    self.build_retries,
+                )
+            })
+        };
+        let bin_path = match bin_path {
+            Ok(bin_path) => bin_path,
+            Err(err) => {
+                if self.cache_failures
+                    && let Some(failed) = err.downcast_ref::<CargoFailed>()
+                    && let Err(record_err) = self.record_failure(failed, sources)
+                    && self.report.verbose
+                {
+                    debug!("Couldn't record the failed build: {record_err:#}");
+                }
+                if (self.settings.offline || self.settings.frozen)
+                    && let Some(failed) = err.downcast_ref::<CargoFailed>()
+                    && is_offline_miss(&failed.output)
+                {
+                    return Err(err.context(
+                        "a dependency isn't downloaded, and --offline/--frozen keep cargo from \
+                         fetching it; build once without them (or with network access) first",
+                    ));
+                }
+                if err.downcast_ref::<CargoFailed>().is_some() && self.binary_went_missing() {
+                    return Err(err.context(
+                        "the cached binary had been deleted (by `cargo clean`, say), which most \
+                         likely took its dependencies' builds with it; if the script's \
+                         dependencies changed since, try again with --update",
+                    ));
+                }
+                return Err(err);
+            }
+        };
+        let build_ms = building.elapsed().as_millis() as u64;
+        let _ = fs::remove_file(failure_path(&self.meta_path));
+        if sources.outdated(&self.script)? {
+            // Cargo may have built either version, so there's nothing trustworthy to record.
+            if !self.report.quiet {
+                note!(
+                    "Warning: {} changed while it was being built; not caching this build",
+                    self.script.display()
+                );
+            }
+            return Ok(Prepared {
+                bin: bin_path,
+                cache_hit: false,
+            });
+        }
+        let fp = self.settings.fingerprint(sources);
+        let bin_path = if self.dedup {
+            self.install_binary(&bin_path, &content_key(&fp))?
+        } else if self.shared_target || self.raw_bin_path {
+            bin_path
+        } else {
+            // A copy under the cache outlives `cargo clean` and anything else that clears
+            // cargo's target dir, and its path doesn't depend on where that is.
+            self.install_binary(&bin_path, &self.bin_name())?
+        };
+
+        if self.report.verbose {
+            debug!("Writing cache metadata");
+        }
+        let source_bytes = source_bytes(&self.script, &fp.modules);
+        self.record_variant(Variant {
+            fp,
+            bin: bin_path.clone(),
+            builder_version: self.builder_version().map(str::to_string),
+            built_at: Some(unix_secs(SystemTime::now())),
+            bin_hash: hash_file(&bin_path).ok(),
+            source_bytes: Some(source_bytes),
+            build_ms: Some(build_ms),
+            bin_bytes: fs::metadata(&bin_path).map(|meta| meta.len()).ok(),
+        })?;
+
+        if let Some(max_bytes) = self.max_cache_size {
+            // Eviction is housekeeping; failing at it shouldn't stop the script from running.
+            match evict_lru(&self.cache_root, &self.meta_path, max_bytes) {
+                Ok(evicted) if self.report.verbose => {
+                    for path in evicted {
+                        debug!("Evicted cache entry: {}", path.display());
+                    }
+                }
+                Ok(_) => {}
+                Err(err) if self.report.verbose => {
+                    debug!("Cache eviction failed: {err:#}")
+                }
+                Err(_) => {}
+            }
+        }
+
+        Ok(Prepared {
+            bin: bin_path,
+            cache_hit: false,
+        })
+    }
+
+    /// Store `variant` as the script's build for the current settings, alongside its builds for
+    /// other settings, except any whose binary this one has just overwritten (e.g. builds with
+    /// other `RUSTFLAGS`, whose binaries land at the same path in cargo's target dir).
+    fn record_variant(&self, variant: Variant) -> Result<()> {
+        let mut meta = read_meta(&self.meta_path).unwrap_or_default();
+        meta.variants.retain(|_, other| other.bin != variant.bin);
+        meta.variants.insert(self.settings.variant(), variant);
+        meta.script = Some(self.script.clone());
+        write_meta(&self.meta_path, &meta)
+    }
+
+    /// The cached binary, if the metadata on disk still describes the script as it is now.
+    fn cached(&self) -> Result<Option<PathBuf>> {
+        let lookup = self.lookup()?;
+        if self.report.verbose {
+            for detail in &lookup.details {
+                debug!("{detail}");
+            }
+            debug!("{}", lookup.verdict);
+        }
+        if let Some(warning) = &lookup.warning
+            && !self.report.quiet
+        {
+            note!("{warning}");
+        }
+        if lookup.bin.is_some() {
+            touch(&self.meta_path);
+        }
+        Ok(lookup.bin)
+    }
+
+    /// What builds binaries here: this scriptr and the toolchain's cargo, e.g. `scriptr 0.1.5,
+    /// cargo 1.90.0-nightly (840b83a10 2025-07-30)`, or `None` if cargo can't say.
+    fn builder_version(&self) -> Option<&str> {
+        self.builder_version
+            .get_or_init(|| {
+                let cargo = self.cargo_version()?;
+                Some(format!("{NAME} {}, {cargo}", env!("CARGO_PKG_VERSION")))
+            })
+            .as_deref()
+    }
+
+    /// What `cargo --version` says for this toolchain. The answer is kept under `versions/`,
+    /// named by [`Toolchain::stamp`], so a cache hit needn't spawn cargo to check its builder.
+    fn cargo_version(&self) -> Option<String> {
+        let memo = self
+            .cache_root
+            .join("versions")
+            .join(self.settings.toolchain.stamp());
+        if let Ok(version) = fs::read_to_string(&memo)
+            && !version.is_empty()
+        {
+            return Some(version);
+        }
+        let version = self
+            .settings
+            .toolchain
+            .preflight(self.report.verbose)
+            .ok()?;
+        // Without the memo the next run just asks cargo again.
+        let tmp = memo.with_extension(format!("{}.new", std::process::id()));
+        let written = fs::create_dir_all(memo.parent().expect("memo has a parent"))
+            .and_then(|()| fs::write(&tmp, &version))
+            .and_then(|()| fs::rename(&tmp, &memo));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        Some(version)
+    }
+
+    /// What `--explain` prints: the decision `prepare` would make, and the evidence for it.
+    fn explain(&self, force: bool, update: bool) -> Result<Vec<String>> {
+        let skipped = if self.no_cache {
+            Some("rebuild: --no-cache never uses the cache")
+        } else if update {
+            Some("rebuild: dependency update requested")
+        } else if force {
+            Some("forced rebuild")
+        } else {
+            None
+        };
+        if let Some(verdict) = skipped {
+            return Ok(vec![verdict.to_string()]);
+        }
+        let Lookup {
+            bin,
+            verdict,
+            mut details,
+            ..
+        } = self.lookup()?;
+        details.push(verdict);
+        if let Some(bin) = bin {
+            details.push(format!("binary: {}", bin.display()));
+        }
+        Ok(details)
+    }
+
+    /// The recorded failure, if it was for the script's current sources and settings.
+    fn cached_failure(&self) -> Result<Option<Failure>> {
+        let Ok(file) = File::open(failure_path(&self.meta_path)) else {
+            return Ok(None);
+        };
+        // A record we can't read is as good as none; the next failure overwrites it.
+        let Ok(failure) = serde_json::from_reader::<_, Failure>(BufReader::new(file)) else {
+            return Ok(None);
+        };
+        if !self.settings.matches(&failure.fp) || failure.fp.hash_algo != self.hash_algo.name() {
+            return Ok(None);
+        }
+        let sources = timed(self.report.verbose, "hash", || {
+            fingerprint_sources(&self.script, self.hash_algo)
+        })?;
+        Ok((sources.hash == failure.fp.hash).then_some(failure))
+    }
+
+    /// Remember that the script's current sources failed to build, for [`Job::cached_failure`].
+    fn record_failure(&self, failed: &CargoFailed, sources: Sources) -> Result<()> {
+        let failure = Failure {
+            fp: self.settings.fingerprint(sources),
+            code: failed.status.code(),
+            output: failed.output.clone(),
+        };
+        fs::write(failure_path(&self.meta_path), serde_json::to_vec(&failure)?)?;
+        Ok(())
+    }
+
+    /// Whether the metadata records a build with these settings whose binary has since been
+    /// deleted, as by a `cargo clean` (with `--raw-bin-path`) or a cache cleaner.
+    fn binary_went_missing(&self) -> bool {
+        read_meta(&self.meta_path)
+            .ok()
+            .and_then(|mut meta| meta.variants.remove(&self.settings.variant()))
+            .is_some_and(|variant| !variant.bin.exists())
+    }
+
+    /// Why a binary built at `built_at` is too old for `--rebuild-if-older-than`, if it is. One
+    /// whose age isn't known counts as too old.
+    fn stale(&self, built_at: Option<u64>) -> Option<String> {
+        let max_age = self.max_age?.as_secs();
+        let Some(built_at) = built_at else {
+            return Some(format!(
+                "cache miss: binary's age is unknown, and --rebuild-if-older-than is {max_age}s"
+            ));
+        };
+        let age = unix_secs(SystemTime::now()).saturating_sub(built_at);
+        (age > max_age).then(|| {
+            format!("cache miss: binary is {age}s old, over --rebuild-if-older-than's {max_age}s")
+        })
+    }
+
+    /// Compare the cache entry against the script, without side effects.
+    fn lookup(&self) -> Result<Lookup> {
+        let miss = |verdict: String, details| Lookup {
+            bin: None,
+            verdict,
+            details,
+            warning: None,
+        };
+        let mut meta = match read_meta(&self.meta_path) {
+            // Any unreadable entry is just a miss; rebuilding overwrites it.
+            Err(err) => {
+                let verdict = match err.downcast_ref::<std::io::Error>() {
+                    Some(io) if io.kind() == std::io::ErrorKind::NotFound => {
+                        "cache miss: no cache found".to_string()
+                    }
+                    _ => format!("cache miss: ignoring unusable cache: {err:#}"),
+                };
+                return Ok(miss(verdict, Vec::new()));
+            }
+            Ok(meta) => meta,
+        };
+        let Some(meta) = meta.variants.remove(&self.settings.variant()) else {
+            return Ok(miss(
+                format!(
+                    "cache miss: no cached build with profile {:?}, toolchain {:?}, target {:?}, rustflags {:?}, features {:?}",
+                    self.settings.profile(),
+                    self.settings.toolchain.cache_id(),
+                    self.settings.target.as_deref().unwrap_or_default(),
+                    self.settings.rustflags,
+                    self.settings.features.id()
+                ),
+                Vec::new(),
+            ));
+        };
+        if !self.settings.matches(&meta.fp) {
+            return Ok(miss(
+                format!(
+                    "cache miss: cached build used profile {:?}, toolchain {:?}, cargo args {:?}, target {:?}, rustflags {:?}, offline {}, frozen {}, isolated {}, features {:?}, registry {:?}, edition {:?}",
+                    meta.fp.profile,
+                    meta.fp.toolchain,
+                    meta.fp.cargo_args,
+                    meta.fp.target,
+                    meta.fp.rustflags,
+                    meta.fp.offline,
+                    meta.fp.frozen,
+                    meta.fp.isolated,
+                    meta.fp.features,
+                    meta.fp.registry,
+                    meta.fp.edition
+                ),
+                Vec::new(),
+            ));
+        }
+        if meta.fp.hash_algo != self.hash_algo.name() {
+            // Digests from different algorithms can't be compared.
+            return Ok(miss(
+                format!(
+                    "cache miss: cached hash is {}, but {} was requested",
+                    meta.fp.hash_algo,
+                    self.hash_algo.name()
+                ),
+                Vec::new(),
+            ));
+        }
+        // A failing `cargo --version` can't tell us anything, so it doesn't cost the entry.
+        if let Some(current) = self.builder_version()
+            && meta.builder_version.as_deref() != Some(current)
+        {
+            let cached = meta
+                .builder_version
+                .as_deref()
+                .unwrap_or("an older scriptr");
+            return Ok(Lookup {
+                warning: Some(format!(
+                    "Cached binary was built by {cached}; rebuilding with {current}"
+                )),
+                ..miss(
+                    format!("cache miss: built by {cached}, but this is {current}"),
+                    Vec::new(),
+                )
+            });
+        }
+        if !meta.bin.exists() {
+            return Ok(miss(
+                format!(
+                    "cache miss: binary {} is missing (deleted by `cargo clean`?), rebuilding",
+                    meta.bin.display()
+                ),
+                Vec::new(),
+            ));
+        }
+        if let Some(dir) = self.settings.profile.foreign_dir(&meta.bin) {
+            return Ok(miss(
+                format!(
+                    "cache miss: binary {} is in cargo's {dir}/ dir, but this is a {} build",
+                    meta.bin.display(),
+                    self.settings.profile()
+                ),
+                Vec::new(),
+            ));
+        }
+        if let Some(verdict) = self.stale(meta.built_at) {
+            return Ok(miss(verdict, Vec::new()));
+        }
+
+        let mut details = Vec::new();
+        let mut mtime_known = false;
+        // Check mtime first (unless in hash-only mode)
+        let mtime_changed = if self.hash_only {
+            details.push("hash-only mode, skipping mtime".to_string());
+            true
+        } else if let Some(cur_mtime) = mtime_if_supported(&self.script)? {
+            mtime_known = true;
+            details.push(format!(
+                "cached mtime: {}, current mtime: {cur_mtime}",
+                meta.fp.mtime_ns
+            ));
+            let changed_module = meta
+                .fp
+                .modules
+                .iter()
+                .find(|m| mtime_nanos(&m.path).ok() != Some(m.mtime_ns));
+            if let Some(module) = changed_module {
+                details.push(format!("module {} changed", module.path.display()));
+            }
+            meta.fp.mtime_ns != cur_mtime || changed_module.is_some()
+        } else {
+            details.push(format!(
+                "warning: no mtime available for {}, comparing hashes instead",
+                self.script.display()
+            ));
+            true
+        };
+        if !mtime_changed {
+            return Ok(Lookup {
+                bin: Some(meta.bin),
+                verdict: "cache hit: mtime matches".to_string(),
+                details,
+                warning: None,
+            });
+        }
+        if self.trust_mtime && mtime_known {
+            return Ok(miss(
+                "cache miss: mtime changed (--trust-mtime skips the hash check)".to_string(),
+                details,
+            ));
+        }
+
+        let cur_hash = timed(self.report.verbose, "hash", || {
+            fingerprint_sources(&self.script, self.hash_algo)
+        })?
+        .hash;
+        details.push(format!(
+            "cached hash: {}, current hash: {}",
+            &meta.fp.hash[..16],
+            &cur_hash[..16]
+        ));
+        if meta.fp.hash == cur_hash {
+            Ok(Lookup {
+                bin: Some(meta.bin),
+                verdict: "cache hit: hash matches".to_string(),
+                details,
+                warning: None,
+            })
+        } else {
+            Ok(miss("cache miss: hash differs".to_string(), details))
+        }
+    }
+
+    /// Build into the cache's shared target dir, then move the binary somewhere stable.
+    ///
+    /// Concurrent builds into one target dir would trample each other, so they're serialized
+    /// with an advisory lock. The binary is then hard-linked (or copied) out to
+    /// `bin/<cache key>`: scripts with the same file stem produce the same artifact path in a
+    /// shared target, so `meta.bin` can't point into it without being overwritten by the next
+    /// build of a namesake script (see [`BuildSettings::shared_build_command`]).
+    fn rebuild_shared(&self) -> Result<PathBuf> {
+        let lock = File::create(self.cache_root.join("target.lock"))?;
+        if self.report.verbose {
+            debug!(
+                "Shared target: {}",
+                self.cache_root.join("target").display()
+            );
+        }
+        let cmd = self.cargo_build_command();
+        lock.lock_exclusive()?;
+        let built = timed(self.report.verbose, "cargo build", || {
+            rebuild(
+                cmd,
+                &self.script,
+                &self.settings.toolchain,
+                self.report,
+                self.build_retries,
+            )
+        })?;
+
+        let stable = self.install_binary(&built, &self.bin_name())?;
+        FileExt::unlock(&lock)?;
+        Ok(stable)
+    }
+
+    /// The `cargo` invocation a rebuild runs: into a scratch or the shared target dir when asked,
+    /// otherwise cargo's own.
+    fn cargo_build_command(&self) -> Command {
+        let verbose = self.report.verbose;
+        if self.shared_target && !self.no_cache {
+            let key = self
+                .meta_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .expect("cache key is hex");
+            let target_dir = self.cache_root.join("target");
+            return self
+                .settings
+                .shared_build_command(&self.manifest, &target_dir, key, verbose);
+        }
+        let mut cmd = self.settings.build_command(&self.manifest, verbose);
+        if self.no_cache {
+            cmd.env("CARGO_TARGET_DIR", self.cache_root.join("target"));
+        } else if self.settings.features != Features::default() {
+            // Cargo would build every feature set to the same path, each build replacing the
+            // last one's binary, so all but the default features get a target dir of their own.
+            cmd.env("CARGO_TARGET_DIR", self.features_target_dir());
+        }
+        cmd
+    }
+
+    /// Build the script afresh into a scratch target dir for `--verify`, and compare the result
+    /// with its current cached binary.
+    fn verify(&self) -> Result<Verification> {
+        let Some(cached) = self.lookup()?.bin else {
+            anyhow::bail!(
+                "{} has no up-to-date cached build to verify; run it first",
+                self.script.display()
+            );
+        };
+        let recorded = read_meta(&self.meta_path)?
+            .variants
+            .remove(&self.settings.variant())
+            .and_then(|variant| variant.bin_hash);
+        let cached_hash = hash_file(&cached)?;
+
+        self.settings.toolchain.preflight(self.report.verbose)?;
+        let scratch = scratch_dir()?;
+        if self.report.verbose {
+            debug!("Rebuilding in {} to compare", scratch.display());
+        }
+        let mut cmd = self.cargo_build_command();
+        cmd.env("CARGO_TARGET_DIR", scratch.join("target"));
+        let fresh = rebuild(
+            cmd,
+            &self.script,
+            &self.settings.toolchain,
+            self.report,
+            self.build_retries,
+        )
+        .and_then(|bin| hash_file(&bin));
+        // Nothing runs from it, so unlike --no-cache's, this scratch dir can go straight away.
+        let _ = fs::remove_dir_all(&scratch);
+        Ok(Verification {
+            bin: cached,
+            cached: cached_hash,
+            recorded,
+            fresh: fresh?,
+        })
+    }
+
+    /// What `--dry-run` prints: the binary a cache hit would run, or else each cargo command a
+    /// rebuild would.
+    fn dry_run(&self, force: bool, update: bool) -> Result<Vec<String>> {
+        if !(force || update || self.no_cache) {
+            let mut bin = self.lookup()?.bin;
+            if bin.is_none() && self.dedup {
+                let fp = self
+                    .settings
+                    .fingerprint(fingerprint_sources(&self.script, self.hash_algo)?);
+                bin = self.deduped_binary(&fp);
+            }
+            if let Some(bin) = bin {
+                return Ok(vec![bin.display().to_string()]);
+            }
+        }
+        let verbose = self.report.verbose;
+        let mut commands = Vec::new();
+        if update {
+            let cmd = self.settings.update_command(&self.manifest, verbose);
+            commands.push(render_command(&cmd));
+        }
+        commands.push(render_command(&self.cargo_build_command()));
+        Ok(commands)
+    }
+
+    /// The name the binary for these settings is kept under in `bin/`: `<cache key>-<variant
+    /// hash>`. Each variant gets its own copy: a release build mustn't replace the debug binary.
+    fn bin_name(&self) -> String {
+        let key = self
+            .meta_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .expect("cache key is hex");
+        let variant = blake3::hash(self.settings.variant().as_bytes()).to_hex();
+        format!("{key}-{}", &variant[..16])
+    }
+
+    /// Where a build with other than the default features goes:
+    /// `targets/<cache key>/<variant hash>` beside the metadata, removed along with it.
+    fn features_target_dir(&self) -> PathBuf {
+        let variant = blake3::hash(self.settings.variant().as_bytes()).to_hex();
+        targets_dir(&self.meta_path).join(&variant[..16])
+    }
+
+    /// Hard-link (or failing that, copy) `built` to `bin/<name>` in the cache, where cargo won't
+    /// overwrite it, keeping its extension.
+    fn install_binary(&self, built: &Path, name: &str) -> Result<PathBuf> {
+        let bin_dir = self.cache_root.join("bin");
+        fs::create_dir_all(&bin_dir)?;
+        let mut stable = bin_dir.join(name);
+        if let Some(ext) = built.extension() {
+            stable.set_extension(ext);
+        }
+        let _ = fs::remove_file(&stable);
+        if fs::hard_link(built, &stable).is_err() {
+            fs::copy(built, &stable).with_context(|| {
+                format!("cannot copy {} to {}", built.display(), stable.display())
+            })?;
+        }
+        Ok(stable)
+    }
+
+    /// Where `--dedup` keeps the binary for sources and settings fingerprinted as `fp`, if one
+    /// has been built. Copies of a script anywhere share it.
+    fn deduped_binary(&self, fp: &Fingerprint) -> Option<PathBuf> {
+        let bin_dir = self.cache_root.join("bin");
+        let name = content_key(fp);
+        [
+            bin_dir.join(&name),
+            bin_dir.join(&name).with_extension("exe"),
+        ]
+        .into_iter()
+        .find(|path| path.is_file())
+    }
+}
+
+/* ------------------------------------------------------------------------- */
+
+/// The directory holding scriptr's metadata, created if necessary.
+///
+/// That's `dir` (`--cache-dir`, `SCRIPTR_CACHE_DIR` or the config file) if given. Otherwise it's
+/// `scriptr/` in the platform cache dir or, failing that, in the temp dir (`TMPDIR`); if neither
+/// is usable, the error says what went wrong with each.
+fn cache_root(dir: Option<PathBuf>) -> Result<PathBuf> {
+    if let Some(root) = dir {
+        prepare_cache_root(&root)
+            .with_context(|| format!("cannot create cache directory {}", root.display()))?;
+        return Ok(root);
+    }
+    let platform = cache_dir();
+    let mut failures = Vec::new();
+    if platform.is_none() {
+        failures.push("  the platform cache dir: unknown (is HOME set?)".to_string());
+    }
+    for root in platform.into_iter().chain([std::env::temp_dir()]) {
+        let root = root.join(NAME);
+        match prepare_cache_root(&root) {
+            Ok(()) => return Ok(root),
+            Err(err) => failures.push(format!("  {}: {err:#}", root.display())),
+        }
+    }
+    anyhow::bail!(
+        "no usable cache directory; tried:\n{}\nChoose one with --cache-dir or SCRIPTR_CACHE_DIR",
+        failures.join("\n")
+    )
+}
+
+/// Create `root` and check that scriptr can write there. Also warn, the first time a directory is
+/// used, if it won't run executables: binaries kept in the cache (all of them, short of
+/// `--raw-bin-path`) then fail to exec.
+fn prepare_cache_root(root: &Path) -> Result<()> {
+    fs::create_dir_all(root)?;
+    let checked = root.join(".checked");
+    if checked.exists() {
+        return Ok(());
+    }
+    let probe = root.join(format!(".probe-{}", std::process::id()));
+    fs::write(&probe, "#!/bin/sh\n").context("directory is not writable")?;
+    let execs = probe_exec(&probe);
+    let _ = fs::remove_file(&probe);
+    if execs {
+        fs::write(&checked, "")?;
+    } else {
+        note!(
+            "Warning: cache directory {} doesn't allow running programs (mounted noexec?); binaries \
+             kept there will fail to start. Choose another with --cache-dir",
+            root.display()
+        );
+    }
+    Ok(())
+}
+
+/// Whether the file at `probe`, a shell script, can be made executable and run.
+#[cfg(unix)]
+fn probe_exec(probe: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    if fs::set_permissions(probe, fs::Permissions::from_mode(0o755)).is_err() {
+        return false;
+    }
+    !matches!(
+        Command::new(probe).stdin(Stdio::null()).status(),
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied
+    )
+}
+
+/// Windows has no noexec mounts to speak of.
+#[cfg(windows)]
+fn probe_exec(_probe: &Path) -> bool {
+    true
+}
+
+/// A new, empty directory under the system temp dir for a `--no-cache` build.
+///
+/// It is left behind on purpose: on Unix the binary inside is exec'd in place of this process,
+/// and with `--print-path` it has to outlive us anyway, so reaping it is up to the OS's tmp
+/// cleaning.
+fn scratch_dir() -> Result<PathBuf> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    let dir = std::env::temp_dir().join(format!("{NAME}-{}-{nanos}", std::process::id()));
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("cannot create scratch directory {}", dir.display()))?;
+    Ok(dir)
+}
+
+/// The name `--dedup` stores a binary under: a digest of everything in `fp` that decides what
+/// comes out of the build, which leaves out where the script is and when it was modified.
+fn content_key(fp: &Fingerprint) -> String {
+    let identity = (
+        &fp.hash,
+        &fp.hash_algo,
+        &fp.profile,
+        &fp.toolchain,
+        &fp.cargo_args,
+        &fp.target,
+        &fp.rustflags,
+        (fp.offline, fp.frozen, fp.isolated),
+        &fp.features,
+        &fp.registry,
+        &fp.edition,
+    );
+    let encoded = serde_json::to_vec(&identity).expect("strings serialize");
+    blake3::hash(&encoded).to_hex().to_string()
+}
+
+/// Where cargo should run for `script` (see [`BuildSettings::isolated_dir`]): the cache root,
+/// when `--isolated` asks for it or the script sits beside a `Cargo.toml` (not being one).
+/// Cargo already builds a script as its own package, but reads `.cargo/config.toml` from the
+/// directory it runs in and that directory's ancestors, so from inside the surrounding project
+/// its settings would apply.
+fn isolated_dir(isolated: bool, script: &Path, cache_root: &Path) -> Option<PathBuf> {
+    // A package's own `.cargo/config.toml` is meant for it, though.
+    let beside_manifest = !is_cargo_manifest(script)
+        && script
+            .parent()
+            .is_some_and(|dir| dir.join("Cargo.toml").is_file());
+    (isolated || beside_manifest).then(|| cache_root.to_path_buf())
+}
+
+/// The metadata file for a script identified by `key` of the given `kind` ("id", "stdin",
+/// "stdin-name", "eval" or "path") and built with `toolchain`.
+fn meta_path_for(cache_root: &Path, kind: &str, key: &[u8], toolchain: &Toolchain) -> PathBuf {
+    let mut hasher = Hasher::new();
+    hasher.update(kind.as_bytes());
+    hasher.update(b":");
+    hasher.update(key);
+    // Different toolchains produce different binaries, so they get separate entries.
+    hasher.update(b"\0toolchain:");
+    hasher.update(toolchain.cache_id().as_bytes());
+    cache_root.join(format!("{}.json", hasher.finalize().to_hex()))
+}
+
+/// Where the script whose metadata is at `meta_path` keeps its `Cargo.lock`:
+/// `locks/<cache key>/Cargo.lock` beside the metadata (cargo insists on the file name).
+///
+/// Cargo would otherwise put it in its own per-script build directory. Keeping it here means
+/// dependency versions stay pinned for as long as the cache entry lives, and go with it.
+fn lockfile_path(meta_path: &Path) -> PathBuf {
+    let key = meta_path.file_stem().expect("metadata file has a name");
+    meta_path
+        .with_file_name("locks")
+        .join(key)
+        .join("Cargo.lock")
+}
+
+/// The target dirs of the script whose metadata is at `meta_path`, as far as it has any of its
+/// own: `targets/<cache key>` (see [`Job::features_target_dir`]).
+fn targets_dir(meta_path: &Path) -> PathBuf {
+    let key = meta_path.file_stem().expect("metadata file has a name");
+    meta_path.with_file_name("targets").join(key)
+}
+
+/// Delete what's kept beside the script's metadata: its cached `Cargo.lock` (with its
+/// directory), its own target dirs and its recorded failure, if any.
+fn remove_sidecars(meta_path: &Path) {
+    if let Some(dir) = lockfile_path(meta_path).parent() {
+        let _ = fs::remove_dir_all(dir);
+    }
+    let _ = fs::remove_dir_all(targets_dir(meta_path));
+    let _ = fs::remove_file(failure_path(meta_path));
+}
+
+/// `--clean`: delete the script's metadata and its sidecars (see [`remove_sidecars`]), saying
+/// whether it had metadata to delete. Unlike [`remove_sidecars`], anything that's there but
+/// can't be removed is an error.
+fn clean_entry(meta_path: &Path) -> Result<bool> {
+    let remove =
+        |path: &Path, removed: std::io::Result<()>| match removed {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(anyhow::Error::new(err)
+                .context(format!("cannot clean the cache: {}", path.display()))),
+            _ => Ok(()),
+        };
+    let existed = meta_path.exists();
+    remove(meta_path, fs::remove_file(meta_path))?;
+    if let Some(dir) = lockfile_path(meta_path).parent() {
+        remove(dir, fs::remove_dir_all(dir))?;
+    }
+    let targets = targets_dir(meta_path);
+    remove(&targets, fs::remove_dir_all(&targets))?;
+    let failure = failure_path(meta_path);
+    remove(&failure, fs::remove_file(&failure))?;
+    Ok(existed)
+}
+
+/// Delete whatever under `links/` an entry's `script` was built through: see [`manifest_path`]
+/// and [`edition_manifest`]. Another entry for the same script just recreates it on its next run.
+fn remove_link(cache_root: &Path, script: Option<&Path>) {
+    let Some(script) = script else {
+        return;
+    };
+    let _ = fs::remove_dir_all(link_dir(cache_root, script));
+    // Older entries recorded the link itself as the script.
+    if let Some(dir) = script.parent()
+        && dir.parent() == Some(&cache_root.join("links"))
+    {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+/// How long a temp file has to go untouched before `cache prune` takes it for a leftover of a
+/// run that was killed, rather than one still being written.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Files under the cache that killed runs left behind (see [`DISPOSABLE`]): half-written `*.new`
+/// files, and scripts staged from stdin or `--eval` that no entry in `kept` was built from. Only
+/// those older than [`STALE_TEMP_AGE`], so a run still in progress keeps its own.
+fn stray_temp_files(root: &Path, kept: &[PathBuf]) -> Vec<PathBuf> {
+    let mut stray = Vec::new();
+    for dir in [root.to_path_buf(), root.join("stdin"), root.join("eval")] {
+        let Ok(listing) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in listing.flatten() {
+            let path = entry.path();
+            let ext = path.extension().and_then(OsStr::to_str);
+            let leftover =
+                ext == Some("new") || (dir != root && ext == Some("rs") && !kept.contains(&path));
+            let stale = entry
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age >= STALE_TEMP_AGE);
+            if leftover && stale && entry.file_type().is_ok_and(|kind| kind.is_file()) {
+                stray.push(path);
+            }
+        }
+    }
+    stray.sort();
+    stray
+}
+
+/// Where `--cache-failures` records the last failed build of the script whose metadata is at
+/// `meta_path`.
+fn failure_path(meta_path: &Path) -> PathBuf {
+    meta_path.with_extension("failed")
+}
+
+/// Run a `scriptr cache` subcommand.
+fn cache_command(command: CacheCommand, cache_dir: Option<PathBuf>) -> Result<()> {
+    let root = cache_root(cache_dir)?;
+    let entries = cache_entries(&root)?;
+    match command {
+        CacheCommand::List => {
+            for (_, meta) in &entries {
+                for variant in meta.variants.values() {
+                    let size = fs::metadata(&variant.bin).map(|m| m.len()).ok();
+                    println!(
+                        "{}\t{}\t{}\t{}",
+                        display_script(meta.script.as_deref()),
+                        variant.bin.display(),
+                        size.map_or_else(|| "missing".to_string(), format_bytes),
+                        variant
+                            .build_ms
+                            .map_or_else(|| "-".to_string(), format_build_time)
+                    );
+                }
+            }
+        }
+        CacheCommand::Info { script } => {
+            let script = resolve_script(&script, true)?;
+            let mut found = false;
+            for (path, meta) in &entries {
+                if meta.script.as_deref() != Some(script.as_path()) {
+                    continue;
+                }
+                if found {
+                    println!();
+                }
+                found = true;
+                println!("script:    {}", script.display());
+                println!("metadata:  {}", path.display());
+                for (key, variant) in &meta.variants {
+                    println!("variant:   {key}");
+                    println!("  binary:    {}", variant.bin.display());
+                    println!("  profile:   {}", variant.fp.profile);
+                    println!("  toolchain: {}", variant.fp.toolchain);
+                    println!("  mtime_ns:  {}", variant.fp.mtime_ns);
+                    println!(
+                        "  hash:      {} ({})",
+                        variant.fp.hash, variant.fp.hash_algo
+                    );
+                    if let Some(bytes) = variant.source_bytes {
+                        println!("  source:    {}", format_bytes(bytes));
+                    }
+                    if let Some(bytes) = variant.bin_bytes {
+                        println!("  bin size:  {}", format_bytes(bytes));
+                    }
+                    if let Some(ms) = variant.build_ms {
+                        println!("  build:     {}", format_build_time(ms));
+                    }
+                }
+            }
+            if !found {
+                anyhow::bail!("no cache entry for {}", script.display());
+            }
+        }
+        CacheCommand::Prune => {
+            let mut kept = Vec::new();
+            for (path, mut meta) in entries {
+                let script_gone = meta.script.as_ref().is_some_and(|s| !s.exists());
+                let variants = meta.variants.len();
+                meta.variants.retain(|_, variant| variant.bin.exists());
+                if !script_gone && !meta.variants.is_empty() {
+                    if meta.variants.len() < variants {
+                        write_meta(&path, &meta)?;
+                        let pruned = variants - meta.variants.len();
+                        println!(
+                            "removed {pruned} of {variants} builds of {} (binary missing)",
+                            display_script(meta.script.as_deref())
+                        );
+                    }
+                    kept.extend(meta.script);
+                    continue;
+                }
+                fs::remove_file(&path)?;
+                remove_sidecars(&path);
+                remove_link(&root, meta.script.as_deref());
+                let reason = if script_gone {
+                    "script missing"
+                } else {
+                    "binary missing"
+                };
+                println!(
+                    "removed {} ({reason})",
+                    display_script(meta.script.as_deref())
+                );
+            }
+            for path in stray_temp_files(&root, &kept) {
+                fs::remove_file(&path)?;
+                println!("removed {} (stray temp file)", path.display());
+            }
+        }
+        CacheCommand::Gc {
+            older_than,
+            max_size,
+            orphans,
+            dry_run,
+        } => {
+            let policy = GcPolicy {
+                older_than,
+                max_size,
+                orphans,
+            };
+            let removals = plan_removals(&root, &policy, None)?;
+            let (verb, total) = if dry_run {
+                ("would remove", "would reclaim")
+            } else {
+                ("removed", "reclaimed")
+            };
+            for removal in &removals {
+                if !dry_run {
+                    removal.apply(&root)?;
+                }
+                println!(
+                    "{verb} {} ({}, {})",
+                    display_script(removal.script.as_deref()),
+                    removal.reason,
+                    format_bytes(removal.bytes)
+                );
+            }
+            let reclaimed = removals.iter().map(|r| r.bytes).sum();
+            println!(
+                "{total} {} from {} entries",
+                format_bytes(reclaimed),
+                removals.len()
+            );
+        }
+        CacheCommand::Clear { yes } => {
+            let contents = cache_contents(&root)?;
+            let bytes = contents.iter().map(|path| tree_size(path)).sum();
+            if !yes && std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+                eprint!(
+                    "Remove {} entries ({}) from {}? [y/N] ",
+                    entries.len(),
+                    format_bytes(bytes),
+                    root.display()
+                );
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                    println!("nothing removed");
+                    return Ok(());
+                }
+            }
+            for path in &contents {
+                if path.is_dir() {
+                    fs::remove_dir_all(path)
+                } else {
+                    fs::remove_file(path)
+                }
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+            }
+            println!(
+                "reclaimed {} from {} entries",
+                format_bytes(bytes),
+                entries.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// What in `cache_root` is scriptr's, for `scriptr cache clear`. That goes by scriptr's own
+/// naming, so unrelated files in a shared directory stay: metadata, build locks and recorded
+/// failures (`<key>.json`, `<key>.lock`, `<key>.failed`), the directories holding binaries,
+/// lockfiles, target dirs, links, staged scripts and cargo versions, and the shared target dir
+/// with its lock.
+fn cache_contents(cache_root: &Path) -> Result<Vec<PathBuf>> {
+    const DIRS: [&str; 8] = [
+        "bin", "locks", "targets", "links", "stdin", "eval", "target", "versions",
+    ];
+    let is_key = |stem: &str| stem.len() == 64 && stem.bytes().all(|b| b.is_ascii_hexdigit());
+    let mut contents = Vec::new();
+    for entry in fs::read_dir(cache_root)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+        let ours = match name.rsplit_once('.') {
+            Some((stem, "json" | "failed" | "lock")) => is_key(stem),
+            _ => name == "target.lock" || (DIRS.contains(&name) && path.is_dir()),
+        };
+        if ours {
+            contents.push(path);
+        }
+    }
+    contents.sort();
+    Ok(contents)
+}
+
+/// The bytes in the file at `path`, or in all the files under it.
+fn tree_size(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::read_dir(path)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| tree_size(&entry.path()))
+            .sum(),
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Every readable metadata entry under `cache_root`, sorted by script path.
+fn cache_entries(cache_root: &Path) -> Result<Vec<(PathBuf, Meta)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(cache_root)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json")
+            && let Ok(meta) = read_meta(&path)
+        {
+            entries.push((path, meta));
+        }
+    }
+    entries.sort_by(|(_, a), (_, b)| a.script.cmp(&b.script));
+    Ok(entries)
+}
+
+/// Mark a metadata entry as just used; its mtime is the LRU clock for eviction.
+fn touch(meta_path: &Path) {
+    // Best effort: a stale access time only makes the entry a likelier eviction candidate.
+    if let Ok(f) = File::options().write(true).open(meta_path) {
+        let _ = f.set_modified(SystemTime::now());
+    }
+}
+
+/// Evict least-recently-used entries (metadata and binary) until the cache totals at most
+/// `max_bytes`, never touching `keep`. Returns the metadata paths that were removed.
+///
+/// An entry goes as a whole, with the binaries of all its variants. Several entries may share a
+/// binary (see `--dedup`): it's counted once, and only deleted along with the last entry that
+/// refers to it.
+fn evict_lru(cache_root: &Path, keep: &Path, max_bytes: u64) -> Result<Vec<PathBuf>> {
+    let policy = GcPolicy {
+        max_size: Some(max_bytes),
+        ..GcPolicy::default()
+    };
+    let removals = plan_removals(cache_root, &policy, Some(keep))?;
+    for removal in &removals {
+        removal.apply(cache_root)?;
+    }
+    Ok(removals.into_iter().map(|r| r.meta_path).collect())
+}
+
+/// Which entries `scriptr cache gc` (and eviction after a build) removes. The policies add up:
+/// an entry goes if any of them picks it.
+#[derive(Default)]
+struct GcPolicy {
+    /// Entries not used for this long.
+    older_than: Option<Duration>,
+    /// Then the least recently used, until the cache totals at most this many bytes.
+    max_size: Option<u64>,
+    /// Entries whose script, or every binary, is gone.
+    orphans: bool,
+}
+
+/// A cache entry picked for removal by [`plan_removals`].
+struct Removal {
+    meta_path: PathBuf,
+    /// The script it was built from, as recorded.
+    script: Option<PathBuf>,
+    reason: String,
+    /// Its binaries that no other entry still refers to, which go with it.
+    bins: Vec<PathBuf>,
+    /// What removing it frees: the metadata and `bins`.
+    bytes: u64,
+}
+
+impl Removal {
+    fn apply(&self, cache_root: &Path) -> Result<()> {
+        fs::remove_file(&self.meta_path)?;
+        remove_sidecars(&self.meta_path);
+        remove_link(cache_root, self.script.as_deref());
+        for bin in &self.bins {
+            if bin.exists() {
+                fs::remove_file(bin)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decide which entries under `cache_root` `policy` removes, never picking `keep`, without
+/// removing anything. Entries picked for their age or as orphans come first, then the least
+/// recently used (by metadata mtime; see [`touch`]) while the cache is over `max_size`.
+fn plan_removals(
+    cache_root: &Path,
+    policy: &GcPolicy,
+    keep: Option<&Path>,
+) -> Result<Vec<Removal>> {
+    let file_size = |p: &Path| fs::metadata(p).map(|m| m.len()).unwrap_or(0);
+    let mut entries = Vec::new();
+    // Each binary's size and how many entries refer to it.
+    let mut bins = std::collections::HashMap::<PathBuf, (u64, usize)>::new();
+    let mut total = 0;
+    for (path, meta) in cache_entries(cache_root)? {
+        let mut entry_bins: Vec<PathBuf> = meta.variants.values().map(|v| v.bin.clone()).collect();
+        entry_bins.sort();
+        entry_bins.dedup();
+        for bin in &entry_bins {
+            let (_, refs) = bins.entry(bin.clone()).or_insert_with(|| {
+                let size = file_size(bin);
+                total += size;
+                (size, 0)
+            });
+            *refs += 1;
+        }
+        let size = file_size(&path);
+        total += size;
+        let accessed = fs::metadata(&path)?.modified()?;
+        entries.push((accessed, path, meta, entry_bins, size));
+    }
+    entries.sort_by_key(|(accessed, ..)| *accessed);
+
+    let now = SystemTime::now();
+    let mut removals = Vec::new();
+    let mut remove = |(_, path, meta, entry_bins, size): (SystemTime, PathBuf, Meta, _, u64),
+                      reason: String,
+                      total: &mut u64| {
+        let mut bytes = size;
+        let mut freed = Vec::new();
+        for bin in entry_bins {
+            let (bin_size, refs) = bins.get_mut(&bin).expect("counted above");
+            *refs -= 1;
+            if *refs == 0 {
+                bytes += *bin_size;
+                freed.push(bin);
+            }
+        }
+        *total -= bytes;
+        removals.push(Removal {
+            meta_path: path,
+            script: meta.script,
+            reason,
+            bins: freed,
+            bytes,
+        });
+    };
+
+    let mut kept = Vec::new();
+    for entry in entries {
+        let (accessed, path, meta, ..) = &entry;
+        let unused = now.duration_since(*accessed).unwrap_or_default();
+        let reason = if keep == Some(path.as_path()) {
+            None
+        } else if policy.orphans && meta.script.as_ref().is_some_and(|s| !s.exists()) {
+            Some("script missing".to_string())
+        } else if policy.orphans && meta.variants.values().all(|v| !v.bin.exists()) {
+            Some("binary missing".to_string())
+        } else if policy.older_than.is_some_and(|age| unused > age) {
+            Some(format!("unused for {}", format_age(unused)))
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => remove(entry, reason, &mut total),
+            None => kept.push(entry),
+        }
+    }
+    if let Some(max_bytes) = policy.max_size {
+        for entry in kept {
+            if total <= max_bytes {
+                break;
+            }
+            if keep == Some(entry.1.as_path()) {
+                continue;
+            }
+            let reason = format!("over the {} size limit", format_bytes(max_bytes));
+            remove(entry, reason, &mut total);
+        }
+    }
+    Ok(removals)
+}
+
+/// Format a duration for humans in its largest whole unit, e.g. `3d` or `45m`.
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// Parse a byte size with an optional binary suffix: `1048576`, `512K`, `500M`, `2G`.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let shift = match s[digits.len()..].to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        other => return Err(format!("unknown size suffix {other:?}")),
+    };
+    let n: u64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size {s:?}"))?;
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| format!("size {s:?} is too large"))
+}
+
+/// Parse a duration with a unit suffix: `90s`, `30m`, `24h`, `7d`, `2w`. A bare number is in
+/// seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit: u64 = match s[digits.len()..].to_ascii_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        other => {
+            return Err(format!(
+                "unknown duration unit {other:?} (use s, m, h, d or w)"
+            ));
+        }
+    };
+    let n: u64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid duration {s:?}"))?;
+    n.checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration {s:?} is too long"))
+}
+
+/// `time` in whole seconds since the Unix epoch (0 for anything before it).
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// The arguments in an `--args-file`: each line verbatim (line ending aside), with no quoting or
+/// escaping, so an empty line is an empty argument.
+fn read_args_file(path: &Path) -> Result<Vec<OsString>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("cannot read args file {}", path.display()))?;
+    Ok(text.lines().map(OsString::from).collect())
+}
+
+/// The `RUSTFLAGS` a build uses: those from the environment, then `--rustflags`, so where the
+/// two disagree the flag (coming later on rustc's command line) wins.
+fn effective_rustflags(inherited: Option<String>, flag: Option<String>) -> String {
+    [inherited, flag]
+        .into_iter()
+        .flatten()
+        .map(|flags| flags.trim().to_string())
+        .filter(|flags| !flags.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A digest of what decides the registry cargo resolves dependencies from, so switching to a
+/// mirror (say) rebuilds: `CARGO_HOME`, `CARGO_NET_OFFLINE` and the mtime of the cargo home's
+/// `config.toml`. Only the mtime, to keep this cheap; project-level `.cargo/config.toml` files
+/// aren't tracked either.
+fn registry_id() -> String {
+    let home = std::env::var_os("CARGO_HOME");
+    let offline = std::env::var_os("CARGO_NET_OFFLINE");
+    let config = home
+        .clone()
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|dir| dir.join(".cargo")))
+        .and_then(|dir| {
+            // Cargo still reads the extensionless name when that's all there is.
+            ["config.toml", "config"]
+                .iter()
+                .find_map(|name| mtime_nanos(&dir.join(name)).ok())
+        });
+    let identity = format!("{home:?} {offline:?} {config:?}");
+    blake3::hash(identity.as_bytes()).to_hex()[..16].to_string()
+}
+
+/// The combined size of `script` and its `modules`, skipping any that have since gone.
+fn source_bytes(script: &Path, modules: &[ModuleStamp]) -> u64 {
+    std::iter::once(script)
+        .chain(modules.iter().map(|module| module.path.as_path()))
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+/// A build duration as `cache list` and `cache info` show it, e.g. `12.34s`.
+fn format_build_time(ms: u64) -> String {
+    format!("{:.2}s", ms as f64 / 1e3)
+}
+
+/// The BLAKE3 digest of the file at `path`, in hex.
+fn hash_file(path: &Path) -> Result<String> {
+    let mut hasher = Hasher::new();
+    hasher
+        .update_reader(File::open(path)?)
+        .with_context(|| format!("cannot read {}", path.display()))?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// `cmd` as a line to paste into a POSIX shell: a `cd` to its directory if it has one, then the
+/// environment it sets, the program and its arguments, each quoted where need be.
+fn render_command(cmd: &Command) -> String {
+    let mut words = Vec::new();
+    if let Some(dir) = cmd.get_current_dir() {
+        words.push(format!("cd {} &&", shell_quote(dir.as_os_str())));
+    }
+    for (key, value) in cmd.get_envs() {
+        if let Some(value) = value {
+            words.push(format!("{}={}", key.to_string_lossy(), shell_quote(value)));
+        }
+    }
+    words.push(shell_quote(cmd.get_program()));
+    words.extend(cmd.get_args().map(shell_quote));
+    words.join(" ")
+}
+
+/// `word` single-quoted for a POSIX shell, unless it's made only of characters that are safe
+/// bare. Not UTF-8 is shown lossily; there's no pasting those bytes anyway.
+fn shell_quote(word: &OsStr) -> String {
+    let word = word.to_string_lossy();
+    let bare = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(bare) {
+        return word.into_owned();
+    }
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+/// Parse a `--env` value, splitting at the first `=` so values may contain more of them.
+fn parse_env(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got {s:?}")),
+    }
+}
+
+fn display_script(script: Option<&Path>) -> String {
+    script.map_or_else(
+        || "<unknown script>".to_string(),
+        |s| s.display().to_string(),
+    )
+}
+
+/// Format a byte count for humans, e.g. `1.5 MiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Modification time in nanoseconds, so edits within the same second still change it.
+fn mtime_nanos(p: &Path) -> Result<u64> {
+    let context = || format!("failed to read the mtime of {}", p.display());
+    let modified = fs::metadata(p)
+        .and_then(|meta| meta.modified())
+        .with_context(context)?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).with_context(context)?;
+    Ok(u64::try_from(since_epoch.as_nanos())?)
+}
+
+/// [`mtime_nanos`], or `None` where the platform or filesystem doesn't keep mtimes, so that the
+/// caller can fall back to comparing hashes.
+fn mtime_if_supported(p: &Path) -> Result<Option<u64>> {
+    unsupported_as_none(mtime_nanos(p))
+}
+
+fn unsupported_as_none(mtime: Result<u64>) -> Result<Option<u64>> {
+    match mtime {
+        Err(err)
+            if err
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|io| io.kind() == std::io::ErrorKind::Unsupported) =>
+        {
+            Ok(None)
+        }
+        mtime => mtime.map(Some),
+    }
+}
+
+/// Files at least this big are BLAKE3-hashed through a memory map instead of the read loop.
+///
+/// Measured on x86_64 with a warm page cache: the two break even around 256 KiB, and mmap is
+/// ~1.05x faster at 1 MiB, 1.25x at 4 MiB and 1.4-1.5x from 16 MiB. Below the threshold, mapping
+/// costs more than it saves, and typical scripts are a few KiB.
+const MMAP_THRESHOLD: u64 = 1 << 20;
+
+fn file_hash(p: &Path, algo: HashAlgo) -> Result<String> {
+    let context = || format!("failed to hash {}", p.display());
+    let mut file = File::open(p).with_context(context)?;
+    if algo == HashAlgo::Blake3 && file.metadata().with_context(context)?.len() >= MMAP_THRESHOLD {
+        let mut hasher = Hasher::new();
+        // Mapping can fail (e.g. on some special filesystems); streaming always works.
+        if hasher.update_mmap(p).is_ok() {
+            return Ok(hasher.finalize().to_hex().to_string());
+        }
+    }
+    let mut buf = [0u8; 64 * 1024];
+    let mut hasher = algo.hasher();
+    loop {
+        let n = file.read(&mut buf).with_context(context)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+/// The current state of everything a script build is compiled from.
+struct Sources {
+    mtime_ns: u64,
+    /// Hash of the script, folded together with its modules' hashes if it has any.
+    hash: String,
+    algo: HashAlgo,
+    modules: Vec<ModuleStamp>,
+}
+
+impl Sources {
+    /// Whether `script`'s sources have changed since they were fingerprinted as this: by their
+    /// mtimes, or by hashing them again if the script has none.
+    fn outdated(&self, script: &Path) -> Result<bool> {
+        if self.mtime_ns == 0 {
+            return Ok(fingerprint_sources(script, self.algo)?.hash != self.hash);
+        }
+        let mtime = |path| mtime_if_supported(path).ok().flatten().unwrap_or(0);
+        Ok(mtime(script) != self.mtime_ns
+            || self.modules.iter().any(|m| mtime(&m.path) != m.mtime_ns))
+    }
+}
+
+/// Fingerprint `script` together with the sibling files its `mod foo;` declarations pull in (for a
+/// cargo package, its other sources; see [`package_files`]), and its companion file if it has
+/// one.
+///
+/// This is deliberately shallow: only the script's own `mod` declarations are followed (not ones
+/// inside those modules), and `#[path]` attributes are ignored. A script without modules or a
+/// companion hashes exactly as the file alone does.
+fn fingerprint_sources(script: &Path, algo: HashAlgo) -> Result<Sources> {
+    // Without mtimes, lookups always compare hashes, so the stamp just has to be consistent.
+    let mtime_ns = mtime_if_supported(script)?.unwrap_or(0);
+    let mut hash = file_hash(script, algo)?;
+    let src =
+        fs::read(script).with_context(|| format!("failed to read script {}", script.display()))?;
+    let mut modules = Vec::new();
+    let mut hasher = algo.hasher();
+    hasher.update(hash.as_bytes());
+    // The companion file decides how the script is built, so it's tracked like a module.
+    let companion = Some(companion_path(script)).filter(|path| path.is_file());
+    let files = if is_cargo_manifest(script) {
+        package_files(script)
+    } else {
+        module_files(script, &String::from_utf8_lossy(&src))
+    };
+    for path in files.into_iter().chain(companion) {
+        hasher.update(b"\0");
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        hasher.update(b"\0");
+        hasher.update(file_hash(&path, algo)?.as_bytes());
+        modules.push(ModuleStamp {
+            mtime_ns: mtime_if_supported(&path)?.unwrap_or(0),
+            path,
+        });
+    }
+    if !modules.is_empty() {
+        hash = hasher.finish();
+    }
+    Ok(Sources {
+        mtime_ns,
+        hash,
+        algo,
+        modules,
+    })
+}
+
+/// A script's embedded Cargo manifest, and whether it came from the older `//! ```cargo`
+/// doc-comment form rather than `---` frontmatter.
+#[derive(Debug, PartialEq)]
+struct EmbeddedManifest {
+    toml: String,
+    doc_comment: bool,
+}
+
+/// Extract the manifest embedded at the top of `src`: either frontmatter between matching
+/// fences of three or more dashes (the opening one may carry an infostring such as `cargo`), or
+/// a `//! ```cargo` block in the leading doc comment. Only a shebang and blank lines may come
+/// before it. A fence that's never closed is an error.
+fn embedded_manifest(src: &str) -> Result<Option<EmbeddedManifest>> {
+    let mut lines = src.lines().peekable();
+    if lines
+        .peek()
+        .is_some_and(|l| l.starts_with("#!") && !l.starts_with("#!["))
+    {
+        lines.next();
+    }
+    while lines.peek().is_some_and(|l| l.trim().is_empty()) {
+        lines.next();
+    }
+    let Some(first) = lines.next() else {
+        return Ok(None);
+    };
+
+    let dashes = first.len() - first.trim_start_matches('-').len();
+    if dashes >= 3 {
+        let fence = &first[..dashes];
+        let mut body = Vec::new();
+        for line in lines {
+            if line.trim_end() == fence {
+                return Ok(Some(EmbeddedManifest {
+                    toml: body.join("\n"),
+                    doc_comment: false,
+                }));
+            }
+            body.push(line);
+        }
+        anyhow::bail!("the frontmatter opened with `{fence}` is never closed");
+    }
+
+    let doc = |line: &str| {
+        let rest = line.trim_start().strip_prefix("//!")?;
+        Some(rest.strip_prefix(' ').unwrap_or(rest).to_string())
+    };
+    let mut docs = std::iter::once(first)
+        .chain(lines)
+        .map_while(doc)
+        .skip_while(|l| l.trim() != "```cargo");
+    if docs.next().is_none() {
+        return Ok(None);
+    }
+    let mut body = Vec::new();
+    for line in docs {
+        if line.trim() == "```" {
+            return Ok(Some(EmbeddedManifest {
+                toml: body.join("\n"),
+                doc_comment: true,
+            }));
+        }
+        body.push(line);
+    }
+    anyhow::bail!("the ```cargo block in the doc comment is never closed");
+}
+
+/// `--dump-manifest`: print the script's embedded manifest to stdout, warning on stderr when it
+/// isn't valid TOML.
+fn dump_embedded_manifest(script: &Path) -> Result<()> {
+    let src = fs::read_to_string(script)
+        .with_context(|| format!("failed to read script {}", script.display()))?;
+    let Some(manifest) = embedded_manifest(&src)
+        .with_context(|| format!("failed to parse the manifest in {}", script.display()))?
+    else {
+        note!(
+            "{} has no embedded manifest; cargo's defaults apply (no dependencies)",
+            script.display()
+        );
+        return Ok(());
+    };
+    if manifest.doc_comment {
+        note!(
+            "Warning: this is the older `//! ```cargo` manifest form; current cargo only reads \
+             `---` frontmatter"
+        );
+    }
+    if let Err(err) = manifest.toml.parse::<toml::Table>() {
+        note!(
+            "Warning: the embedded manifest isn't valid TOML: {}",
+            err.message()
+        );
+    }
+    println!("{}", manifest.toml);
+    Ok(())
+}
+
+/// Whether `script` is an ordinary cargo package's manifest rather than a single-file package.
+/// Such a package is built as cargo normally would, without `-Zscript`, and should have just
+/// the one binary.
+fn is_cargo_manifest(script: &Path) -> bool {
+    script.file_name().is_some_and(|name| name == "Cargo.toml")
+}
+
+/// What to call the script at `script`: its file stem (`foo` for `foo.rs`), or a package's
+/// directory name.
+fn script_name(script: &Path) -> Option<&OsStr> {
+    if is_cargo_manifest(script)
+        && let Some(dir) = script.parent().and_then(Path::file_name)
+    {
+        return Some(dir);
+    }
+    script.file_stem()
+}
+
+/// The sources of the cargo package whose manifest is `manifest`, besides the manifest itself:
+/// its `Cargo.lock` and every `.rs` file in the package, except under `target/` and hidden
+/// directories. Sorted, so that they hash the same whatever order the directories list them in.
+fn package_files(manifest: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs: Vec<PathBuf> = manifest
+        .parent()
+        .map(Path::to_path_buf)
+        .into_iter()
+        .collect();
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let (path, name) = (entry.path(), entry.file_name());
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                if name != "target" && !name.as_encoded_bytes().starts_with(b".") {
+                    dirs.push(path);
+                }
+            } else if name == "Cargo.lock" || path.extension().is_some_and(|ext| ext == "rs") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Files for the out-of-line `mod name;` declarations in `src`, resolved beside `script` as
+/// `name.rs` or `name/mod.rs`. Declarations whose file doesn't exist are skipped.
+fn module_files(script: &Path, src: &str) -> Vec<PathBuf> {
+    let dir = script.parent().unwrap_or(Path::new("."));
+    src.lines()
+        .filter_map(|line| {
+            let mut decl = line.trim();
+            if let Some(rest) = decl.strip_prefix("pub") {
+                // `pub mod`, `pub(crate) mod`, ...
+                decl = rest.split_once(')').map_or(rest, |(_, r)| r).trim_start();
+            }
+            let name = decl.strip_prefix("mod ")?.strip_suffix(';')?.trim();
+            let name = name.strip_prefix("r#").unwrap_or(name);
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                return None;
+            }
+            [
+                dir.join(format!("{name}.rs")),
+                dir.join(name).join("mod.rs"),
+            ]
+            .into_iter()
+            .find(|p| p.is_file())
+        })
+        .collect()
+}
+
+/// Check that a `--cargo` override can be run. A bare name is left to the PATH lookup.
+fn check_cargo_program(cargo: PathBuf) -> Result<PathBuf> {
+    if cargo.components().count() == 1 {
+        return Ok(cargo);
+    }
+    let meta = fs::metadata(&cargo)
+        .with_context(|| format!("cargo program {} does not exist", cargo.display()))?;
+    #[cfg(unix)]
+    let executable = {
+        use std::os::unix::fs::PermissionsExt;
+        meta.permissions().mode() & 0o111 != 0
+    };
+    #[cfg(not(unix))]
+    let executable = true;
+    if !meta.is_file() || !executable {
+        anyhow::bail!(
+            "cargo program {} is not an executable file",
+            cargo.display()
+        );
+    }
+    // Cargo may be spawned from a toolchain file's directory, so a relative path won't do.
+    Ok(fs::canonicalize(&cargo)?)
+}
+
+/// The script's real path, with symlinks resolved; for a directory, that of the `Cargo.toml` in
+/// it (see [`is_cargo_manifest`]).
+///
+/// Everything downstream — the cache key, mtime and hash — uses this path, so two links to one
+/// file share an entry, and repointing a link switches to the new target's entry. Unless
+/// `canonical` is false (`--no-canonicalize`): then the path is only made absolute, lexically,
+/// which leaves symlinks (and `..` after them) as they are and skips the filesystem lookups
+/// that can be slow on network mounts. Each link then has an entry of its own.
+fn resolve_script(script: &Path, canonical: bool) -> Result<PathBuf> {
+    let resolved = if canonical {
+        fs::canonicalize(script)
+    } else {
+        std::path::absolute(script).and_then(|path| fs::metadata(&path).map(|_| path))
+    };
+    let resolved = resolved.or_else(|err| {
+        if err.kind() == std::io::ErrorKind::NotFound
+            && let Ok(target) = fs::read_link(script)
+        {
+            anyhow::bail!(
+                "symlink target does not exist: {} -> {}",
+                script.display(),
+                target.display()
+            );
+        }
+        Err(err).with_context(|| format!("cannot resolve path {script:?}"))
+    })?;
+    if !resolved.is_dir() {
+        return Ok(resolved);
+    }
+    // A directory is a cargo package, built from its manifest.
+    let manifest = resolved.join("Cargo.toml");
+    if !manifest.is_file() {
+        anyhow::bail!("{} is a directory without a Cargo.toml", script.display());
+    }
+    Ok(manifest)
+}
+
+/// The path to give cargo for `script`: the script itself, unless that path isn't UTF-8.
+///
+/// Cargo can't build a script whose directory isn't valid UTF-8, so such a script is reached
+/// through a symlink (a copy, where there are no symlinks) under `cache_root/links/`, one per
+/// script path. Edits still show through the link, but `mod` files beside the script don't.
+fn manifest_path(cache_root: &Path, script: &Path) -> Result<PathBuf> {
+    if script.to_str().is_some() {
+        return Ok(script.to_path_buf());
+    }
+    let dir = link_dir(cache_root, script);
+    fs::create_dir_all(&dir)?;
+    // Cargo names the package after the file, so keep as much of the name as is usable.
+    let stem = script
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default()
+        .replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_");
+    let stem = if stem.is_empty() { "script" } else { &stem };
+    let link = dir.join(format!("{stem}.rs"));
+    let _ = fs::remove_file(&link);
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(script, &link)?;
+    #[cfg(not(unix))]
+    fs::copy(script, &link)?;
+    dispose_on_failure(&link);
+    Ok(link)
+}
+
+/// Where the stand-ins cargo is given for `script` live, under `cache_root/links/`.
+fn link_dir(cache_root: &Path, script: &Path) -> PathBuf {
+    let key = blake3::hash(script.as_os_str().as_encoded_bytes()).to_hex();
+    cache_root.join("links").join(&key[..16])
+}
+
+/// The path to give cargo for `script` built as `edition`: a copy of `manifest` (see
+/// [`manifest_path`]) whose embedded manifest sets `package.edition`, in the script's
+/// [`link_dir`] beside the other stand-ins.
+///
+/// `-Zscript` takes no `--edition`, and rustc rejects a second one in `RUSTFLAGS`, so the
+/// manifest is the only place cargo will take it from. The copy is only rewritten when it would
+/// change, which keeps cargo's fingerprint of it between runs. Like a link, it can't reach `mod`
+/// files beside the script, and the lines its manifest gains shift the line numbers in cargo's
+/// diagnostics.
+fn edition_manifest(
+    cache_root: &Path,
+    script: &Path,
+    manifest: &Path,
+    edition: &str,
+) -> Result<PathBuf> {
+    if is_cargo_manifest(script) {
+        anyhow::bail!(
+            "--edition only applies to single-file scripts; set `edition` in {}",
+            script.display()
+        );
+    }
+    let src = fs::read_to_string(manifest)
+        .with_context(|| format!("failed to read script {}", script.display()))?;
+    let src = with_edition(&src, edition)
+        .with_context(|| format!("failed to set the edition in {}", script.display()))?;
+
+    let dir = link_dir(cache_root, script).join(format!("edition-{edition}"));
+    fs::create_dir_all(&dir)?;
+    let name = manifest.file_name().unwrap_or(OsStr::new("script.rs"));
+    let path = dir.join(name);
+    if fs::read_to_string(&path).is_ok_and(|current| current == src) {
+        return Ok(path);
+    }
+    let tmp = path.with_extension(format!("rs.{}.new", std::process::id()));
+    let written = fs::write(&tmp, src).and_then(|()| fs::rename(&tmp, &path));
+    if let Err(err) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(err.into());
+    }
+    Ok(path)
+}
+
+/// `src` with `package.edition` set to `edition` in its `---` frontmatter, which it gains if it
+/// has none (current cargo ignores the older doc-comment form, so that doesn't count).
+fn with_edition(src: &str, edition: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut lines = src.split_inclusive('\n').peekable();
+    if lines
+        .peek()
+        .is_some_and(|l| l.starts_with("#!") && !l.starts_with("#!["))
+    {
+        out.extend(lines.next());
+    }
+    while let Some(line) = lines.next_if(|l| l.trim().is_empty()) {
+        out.push_str(line);
+    }
+
+    let first = lines.peek().copied().unwrap_or_default();
+    let dashes = first.len() - first.trim_start_matches('-').len();
+    let mut table = toml::Table::new();
+    let fence = if dashes >= 3 {
+        lines.next();
+        let fence = &first[..dashes];
+        let mut body = String::new();
+        loop {
+            let Some(line) = lines.next() else {
+                anyhow::bail!("the frontmatter opened with `{fence}` is never closed");
+            };
+            if line.trim_end() == fence {
+                break;
+            }
+            body.push_str(line);
+        }
+        table = body
+            .parse()
+            .map_err(|err: toml::de::Error| anyhow::anyhow!("{}", err.message()))?;
+        first.trim_end()
+    } else {
+        "---"
+    };
+    let package = table
+        .entry("package")
+        .or_insert_with(|| toml::Table::new().into());
+    let Some(package) = package.as_table_mut() else {
+        anyhow::bail!("`package` in the embedded manifest isn't a table");
+    };
+    package.insert("edition".to_string(), edition.into());
+
+    out.push_str(fence);
+    out.push('\n');
+    out.push_str(&toml::to_string(&table)?);
+    out.push_str(&fence[..dashes.max(3)]);
+    out.push('\n');
+    out.extend(lines);
+    Ok(out)
+}
+
+/// Write a script that isn't a file of its own (from stdin, or an `--eval` snippet) to a
+/// content-addressed file under `cache_root/<kind>/`, returning its path and hash.
+///
+/// The staged file deliberately outlives the run: cargo keys a script's target directory on its
+/// manifest path, so re-running identical content reuses both our metadata and cargo's build. It
+/// is only removed by `--clean-only` or a failed run (see [`DISPOSABLE`]), and is otherwise
+/// overwritten (atomically) on each run.
+fn stage_script(cache_root: &Path, kind: &str, src: &[u8]) -> Result<(PathBuf, String)> {
+    let hash = blake3::hash(src).to_hex().to_string();
+
+    let dir = cache_root.join(kind);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{kind}_{}.rs", &hash[..16]));
+    let tmp = path.with_extension(format!("rs.{}.new", std::process::id()));
+    let written = fs::write(&tmp, src).and_then(|()| fs::rename(&tmp, &path));
+    if let Err(err) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(err.into());
+    }
+    Ok((path, hash))
+}
+
+/// Load cached metadata, rejecting entries that parse but couldn't describe a usable build.
+///
+/// Binaries inside the cache are recorded relative to its root, the directory holding the
+/// metadata, so they resolve wherever the cache now is. Entries from older versions recorded
+/// them absolute; one that's gone from where it was recorded, but whose `bin/` file is in this
+/// cache's, is taken to have moved with the cache. The next write records it relative.
+fn read_meta(p: &Path) -> Result<Meta> {
+    let f = File::open(p)?;
+    let mut meta: Meta = serde_json::from_reader(BufReader::new(f))
+        .with_context(|| format!("malformed cache metadata {}", p.display()))?;
+    let root = p.parent().unwrap_or(Path::new(""));
+    for variant in meta.variants.values_mut() {
+        if variant.bin.as_os_str().is_empty() {
+            anyhow::bail!("cache metadata {} has no binary path", p.display());
+        }
+        if variant.fp.hash.len() != blake3::OUT_LEN * 2 {
+            anyhow::bail!("cache metadata {} has an invalid hash", p.display());
+        }
+        if variant.bin.is_relative() {
+            variant.bin = root.join(&variant.bin);
+        } else if !variant.bin.exists()
+            && let Some(name) = variant.bin.file_name()
+            && variant.bin.parent().and_then(Path::file_name) == Some(OsStr::new("bin"))
+            && root.join("bin").join(name).is_file()
+        {
+            variant.bin = root.join("bin").join(name);
+        }
+    }
+    Ok(meta)
+}
+
+/// Replace the metadata at `p` with `meta`, so that even after a crash it holds either the old
+/// entry or the new one in full.
+///
+/// The temp file is named per writer, so concurrent writers for one script never share it, and
+/// it's synced to disk before the rename makes it visible.
+fn write_meta(p: &Path, meta: &Meta) -> Result<()> {
+    static WRITES: AtomicU32 = AtomicU32::new(0);
+    let mut meta = meta.clone();
+    if let Some(root) = p.parent() {
+        for variant in meta.variants.values_mut() {
+            if let Ok(relative) = variant.bin.strip_prefix(root) {
+                variant.bin = relative.to_path_buf();
+            }
+        }
+    }
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    let unique = format!(
+        "{}.{}.{nanos}",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    );
+    let tmp = p.with_extension(format!("json.{unique}.new"));
+    let written = (|| -> Result<()> {
+        let f = File::create(&tmp)?;
+        f.lock_exclusive()?;
+        serde_json::to_writer(&f, &meta)?;
+        f.sync_all()?;
+        FileExt::unlock(&f)?;
+        Ok(())
+    })()
+    .and_then(|()| Ok(fs::rename(&tmp, p)?));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return written;
+    }
+    // The rename itself only survives a power loss once the directory is synced too.
+    #[cfg(unix)]
+    if let Some(dir) = p.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Files rustup consults to pin a toolchain, in its order of precedence.
+const TOOLCHAIN_FILES: [&str; 2] = ["rust-toolchain", "rust-toolchain.toml"];
+
+/// The toolchain a script is built with, and how it was chosen.
+#[derive(Debug)]
+enum Toolchain {
+    /// Passed to cargo as `+<name>`: from `--toolchain`, or the `nightly` default.
+    Override(String),
+    /// A rust-toolchain file above the script pins the toolchain; rustup resolves it from there.
+    Pinned { file: PathBuf, contents: String },
+}
+
+impl Toolchain {
+    /// `--toolchain` wins, then the nearest rust-toolchain file, then `nightly`.
+    fn resolve(flag: Option<String>, script: &Path) -> Result<Self> {
+        if let Some(name) = flag {
+            return Ok(Self::Override(name));
+        }
+        for dir in script.ancestors().skip(1) {
+            for name in TOOLCHAIN_FILES {
+                let file = dir.join(name);
+                if file.is_file() {
+                    let contents = fs::read_to_string(&file)
+                        .with_context(|| format!("cannot read {}", file.display()))?;
+                    return Ok(Self::Pinned { file, contents });
+                }
+            }
+        }
+        Ok(Self::Override("nightly".into()))
+    }
+
+    /// A string identifying this toolchain choice, folded into the cache key.
+    fn cache_id(&self) -> String {
+        match self {
+            Self::Override(name) => format!("+{name}"),
+            Self::Pinned { file, contents } => format!("{}\0{}", file.display(), contents.trim()),
+        }
+    }
+
+    /// Check that cargo and this toolchain are installed, with an actionable error if not, and
+    /// return what `cargo --version` says.
+    ///
+    /// Runs `cargo --version` once per process and toolchain, and only when a build is actually
+    /// needed or a cached binary's builder isn't already known (see [`Job::cargo_version`]).
+    fn preflight(&self, verbose: bool) -> Result<String> {
+        static CHECKED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+        let id = self.cache_id();
+        let checked = CHECKED.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((_, version)) = checked.iter().find(|(checked_id, _)| *checked_id == id) {
+            return Ok(version.clone());
+        }
+        drop(checked);
+
+        let output = match self.cargo().arg("--version").output() {
+            Ok(output) => output,
+            Err(err) => {
+                if verbose {
+                    debug!("Spawning cargo failed: {:?}", err.kind());
+                }
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    if CARGO.get().is_some() {
+                        anyhow::bail!("cargo {} not found on PATH", cargo_program().display());
+                    }
+                    anyhow::bail!("cargo not found on PATH — install Rust from https://rustup.rs");
+                }
+                return Err(err).context("failed to run cargo --version");
+            }
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("is not installed") {
+                match self {
+                    Self::Override(name) => anyhow::bail!(
+                        "{name} toolchain not installed — run `rustup toolchain install {name}`"
+                    ),
+                    Self::Pinned { file, .. } => anyhow::bail!(
+                        "toolchain pinned by {} is not installed — run `rustup toolchain install` in that directory",
+                        file.display()
+                    ),
+                }
+            }
+            anyhow::bail!(
+                "cargo --version failed for toolchain {self} ({}): {}",
+                output.status,
+                stderr.trim()
+            );
+        }
+        let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if verbose {
+            debug!("Using {version}");
+        }
+        CHECKED
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((id, version.clone()));
+        Ok(version)
+    }
+
+    /// Check that this toolchain has clippy, suggesting how to add it if not.
+    fn preflight_clippy(&self) -> Result<()> {
+        let output = self
+            .cargo()
+            .args(["clippy", "--version"])
+            .output()
+            .context("failed to run cargo clippy --version")?;
+        if !output.status.success() {
+            let component = match self {
+                Self::Override(name) => format!("rustup component add clippy --toolchain {name}"),
+                Self::Pinned { .. } => "rustup component add clippy".to_string(),
+            };
+            anyhow::bail!("clippy is not installed for toolchain {self} — run `{component}`");
+        }
+        Ok(())
+    }
+
+    /// A `cargo` command that will run under this toolchain.
+    fn cargo(&self) -> Command {
+        let mut cmd = self.command(cargo_program());
+        cmd.env("CARGO_TERM_COLOR", if color() { "always" } else { "never" });
+        cmd
+    }
+
+    /// A digest of what decides the cargo this toolchain runs, cheap enough for every cache hit:
+    /// the choice itself, where `cargo` resolves to on `PATH` and its mtime, and for rustup,
+    /// `RUSTUP_TOOLCHAIN` and the mtimes of its `settings.toml` and of each installed
+    /// toolchain's channel manifest, which installing or updating it rewrites.
+    fn stamp(&self) -> String {
+        let program = cargo_program();
+        let cargo = if program.components().count() > 1 {
+            Some(program.to_path_buf())
+        } else {
+            std::env::var_os("PATH").and_then(|path| {
+                std::env::split_paths(&path)
+                    .map(|dir| dir.join(program))
+                    .find(|candidate| candidate.is_file())
+            })
+        };
+        let cargo_mtime = cargo.as_deref().and_then(|path| mtime_nanos(path).ok());
+        let rustup = std::env::var_os("RUSTUP_HOME")
+            .map(PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|dir| dir.join(".rustup")));
+        let mut manifests = Vec::new();
+        if let Some(toolchains) = rustup.as_ref().map(|dir| dir.join("toolchains"))
+            && let Ok(entries) = fs::read_dir(&toolchains)
+        {
+            for entry in entries.flatten() {
+                let manifest = entry
+                    .path()
+                    .join("lib/rustlib/multirust-channel-manifest.toml");
+                manifests.push((entry.file_name(), mtime_nanos(&manifest).ok()));
+            }
+            manifests.sort();
+        }
+        let settings = rustup.and_then(|dir| mtime_nanos(&dir.join("settings.toml")).ok());
+        let identity = format!(
+            "{} {cargo:?} {cargo_mtime:?} {:?} {settings:?} {manifests:?}",
+            self.cache_id(),
+            std::env::var_os("RUSTUP_TOOLCHAIN"),
+        );
+        blake3::hash(identity.as_bytes()).to_hex()[..32].to_string()
+    }
+
+    /// A `rustc` command that will run under this toolchain.
+    fn rustc(&self) -> Command {
+        self.command(Path::new("rustc"))
+    }
+
+    /// Run `program`, a rustup proxy, under this toolchain.
+    fn command(&self, program: &Path) -> Command {
+        let mut cmd = Command::new(program);
+        match self {
+            Self::Override(name) => {
+                cmd.arg(format!("+{name}"));
+            }
+            // rustup looks for toolchain files from the working directory, not the manifest.
+            Self::Pinned { file, .. } => {
+                cmd.current_dir(file.parent().expect("toolchain file has a parent"));
+            }
+        }
+        cmd
+    }
+}
+
+impl std::fmt::Display for Toolchain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Override(name) => write!(f, "+{name}"),
+            Self::Pinned { file, .. } => write!(f, "pinned by {}", file.display()),
+        }
+    }
+}
+
+/// Which cargo profile a script is built with.
+#[derive(Clone, PartialEq, Debug)]
+enum Profile {
+    Debug,
+    Release,
+    /// `release`, tuned for binary size. A profile of its own (`target/scriptr-small/`), so its
+    /// binaries never overwrite ordinary release ones.
+    Small,
+    /// A profile from `--profile`, most likely defined in the script's own manifest.
+    Named(String),
+}
+
+impl Profile {
+    /// The name recorded in the fingerprint: cargo's own, but for the built-in profiles.
+    fn name(&self) -> &str {
+        match self {
+            Self::Debug => "debug",
+            Self::Release => "release",
+            Self::Small => "scriptr-small",
+            Self::Named(name) => name,
+        }
+    }
+
+    /// The `debug` or `release` directory `bin` is in, if a build with this profile would have
+    /// gone to the other one. The profile is part of the cache key, so this only catches an
+    /// entry that points at the wrong build regardless, such as one hand-edited or written by a
+    /// scriptr that shared a binary between profiles. Copies under the cache's `bin/` dir carry
+    /// no profile in their path, and other profiles' directories aren't cargo's built-in ones.
+    fn foreign_dir<'a>(&self, bin: &'a Path) -> Option<&'a str> {
+        let dir = bin.parent()?.file_name()?.to_str()?;
+        let own = match self {
+            Self::Debug => "debug",
+            Self::Release => "release",
+            Self::Small | Self::Named(_) => return None,
+        };
+        (matches!(dir, "debug" | "release") && dir != own).then_some(dir)
+    }
+}
+
+/// Parse a `--profile` name, mapping cargo's built-in profiles to the ones scriptr knows so that
+/// e.g. `--profile release` shares a cache entry with a plain release build.
+fn parse_profile(s: &str) -> Result<Profile, String> {
+    match s {
+        "" => Err("profile name is empty".to_string()),
+        "dev" => Ok(Profile::Debug),
+        "release" => Ok(Profile::Release),
+        name => Ok(Profile::Named(name.to_string())),
+    }
+}
+
+/// Everything about a build that determines which binary comes out of it.
+struct BuildSettings {
+    toolchain: Toolchain,
+    profile: Profile,
+    /// Extra `cargo build` arguments from `--cargo-arg`.
+    cargo_args: Vec<String>,
+    /// Triple to cross-compile for; the host when `None`.
+    target: Option<String>,
+    /// `RUSTFLAGS` for the build: the inherited ones, then any from `--rustflags`.
+    rustflags: String,
+    /// Where cargo keeps the script's `Cargo.lock` (see [`lockfile_path`]); cargo's own default
+    /// location when `None`.
+    lockfile: Option<PathBuf>,
+    /// Pass `--locked`, so a lockfile that no longer fits the script is an error.
+    locked: bool,
+    /// Pass `--offline`: dependencies must already be downloaded.
+    offline: bool,
+    /// Pass `--frozen`: both `--locked` and `--offline`.
+    frozen: bool,
+    /// Where cargo runs instead of the current directory, which would otherwise decide the
+    /// `.cargo/config.toml` files it reads; see [`isolated_dir`].
+    isolated_dir: Option<PathBuf>,
+    /// Pass `--jobs`; otherwise cargo goes by `CARGO_BUILD_JOBS`, which it inherits. Parallelism
+    /// doesn't change the binary, so unlike the rest this isn't fingerprinted.
+    jobs: Option<u32>,
+    features: Features,
+    /// Which registry setup dependencies resolve against; see [`registry_id`].
+    registry: String,
+    /// The `--edition` to build as instead of the script's own; see [`edition_manifest`].
+    edition: Option<String>,
+}
+
+impl BuildSettings {
+    fn profile(&self) -> &str {
+        self.profile.name()
+    }
+
+    /// What cargo is told `script` is: see [`manifest_path`], and with `--edition`,
+    /// [`edition_manifest`].
+    fn manifest(&self, cache_root: &Path, script: &Path) -> Result<PathBuf> {
+        let manifest = manifest_path(cache_root, script)?;
+        match &self.edition {
+            Some(edition) => edition_manifest(cache_root, script, &manifest, edition),
+            None => Ok(manifest),
+        }
+    }
+
+    /// The fingerprint to record for `sources` built with these settings.
+    fn fingerprint(&self, sources: Sources) -> Fingerprint {
+        Fingerprint {
+            mtime_ns: sources.mtime_ns,
+            hash: sources.hash,
+            hash_algo: sources.algo.name().to_string(),
+            modules: sources.modules,
+            profile: self.profile().to_string(),
+            toolchain: self.toolchain.cache_id(),
+            cargo_args: self.cargo_args.clone(),
+            target: self.target.clone().unwrap_or_default(),
+            rustflags: self.rustflags.clone(),
+            offline: self.offline,
+            frozen: self.frozen,
+            isolated: self.isolated_dir.is_some(),
+            features: self.features.id(),
+            registry: self.registry.clone(),
+            edition: self.edition.clone().unwrap_or_default(),
+        }
+    }
+
+    /// The [`Fingerprint::variant`] a build with these settings would have.
+    fn variant(&self) -> String {
+        variant_key(
+            self.profile(),
+            &self.toolchain.cache_id(),
+            self.target.as_deref().unwrap_or_default(),
+            &self.rustflags,
+            &self.features.id(),
+        )
+    }
+
+    /// Whether `fp` was recorded by a build with these same settings.
+    fn matches(&self, fp: &Fingerprint) -> bool {
+        fp.profile == self.profile()
+            && fp.toolchain == self.toolchain.cache_id()
+            && fp.cargo_args == self.cargo_args
+            && fp.target == self.target.as_deref().unwrap_or_default()
+            && fp.rustflags == self.rustflags
+            && fp.offline == self.offline
+            && fp.frozen == self.frozen
+            && fp.isolated == self.isolated_dir.is_some()
+            && fp.features == self.features.id()
+            && fp.registry == self.registry
+            && fp.edition == self.edition.as_deref().unwrap_or_default()
+    }
+
+    /// The `cargo build` invocation for `script`.
+    ///
+    /// `--cargo-arg` values come straight after the manifest path, ahead of the flags scriptr
+    /// itself relies on (`--message-format=json`, then `--quiet` and `--release`). Any from a `--`
+    /// on come last instead, for the tool cargo drives.
+    fn build_command(&self, script: &Path, verbose: bool) -> Command {
+        self.cargo_command("build", script, verbose)
+    }
+
+    /// The `cargo check` invocation for `script`, for `--check`.
+    fn check_command(&self, script: &Path, verbose: bool) -> Command {
+        self.cargo_command("check", script, verbose)
+    }
+
+    /// The `cargo clippy` invocation for `script`, for `--clippy`.
+    ///
+    /// clippy is an external cargo subcommand, so `-Zscript` doesn't reach the `cargo check` it
+    /// runs; the environment does.
+    fn clippy_command(&self, script: &Path, verbose: bool) -> Command {
+        let mut cmd = self.cargo_command("clippy", script, verbose);
+        cmd.env("CARGO_UNSTABLE_SCRIPT", "true");
+        cmd
+    }
+
+    /// Like [`Self::build_command`], but building into the shared `target_dir`.
+    ///
+    /// Cargo identifies a single-file package by its name, i.e. the script's file stem, so two
+    /// `tool.rs` scripts in one target dir look like the same package and the second build would
+    /// wrongly reuse the first one's artifact. Building via `cargo rustc` lets us give just the
+    /// script's own crate a distinguishing `-C metadata`, which cargo fingerprints, while its
+    /// dependencies stay shareable.
+    fn shared_build_command(
+        &self,
+        script: &Path,
+        target_dir: &Path,
+        key: &str,
+        verbose: bool,
+    ) -> Command {
+        let mut cmd = self.cargo_command("rustc", script, verbose);
+        cmd.env("CARGO_TARGET_DIR", target_dir);
+        if !self.cargo_args.iter().any(|arg| arg == "--") {
+            cmd.arg("--");
+        }
+        cmd.arg("-C").arg(format!("metadata=scriptr-{key}"));
+        cmd
+    }
+
+    /// The `cargo update` invocation for `script`, for `--update`.
+    fn update_command(&self, script: &Path, verbose: bool) -> Command {
+        let mut cmd = self.lockfile_command();
+        if !is_cargo_manifest(script) {
+            cmd.arg("-Zscript");
+        }
+        cmd.args(["update", "--manifest-path"]).arg(script);
+        if !verbose {
+            cmd.arg("--quiet");
+        }
+        self.network_args(&mut cmd);
+        cmd
+    }
+
+    /// Add `--locked`, `--offline` and `--frozen` as requested.
+    fn network_args(&self, cmd: &mut Command) {
+        for (flag, set) in [
+            ("--locked", self.locked),
+            ("--offline", self.offline),
+            ("--frozen", self.frozen),
+        ] {
+            if set {
+                cmd.arg(flag);
+            }
+        }
+    }
+
+    fn cargo_command(&self, subcommand: &str, script: &Path, verbose: bool) -> Command {
+        // Anything from a `--` on is for the tool cargo drives (rustc, clippy), so it goes last.
+        let split = self
+            .cargo_args
+            .iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(self.cargo_args.len());
+        let (cargo_args, tool_args) = self.cargo_args.split_at(split);
+        let mut cmd = self.lockfile_command();
+        // Set for cargo alone; the script itself still sees whatever RUSTFLAGS we inherited.
+        if !self.rustflags.is_empty() {
+            cmd.env("RUSTFLAGS", &self.rustflags);
+        }
+        if !is_cargo_manifest(script) {
+            cmd.arg("-Zscript");
+        }
+        cmd.args([subcommand, "--manifest-path"])
+            .arg(script)
+            .args(cargo_args)
+            .arg(if color() {
+                "--message-format=json-diagnostic-rendered-ansi"
+            } else {
+                "--message-format=json"
+            });
+        if !verbose {
+            cmd.arg("--quiet");
+        }
+        self.network_args(&mut cmd);
+        if let Some(jobs) = self.jobs {
+            cmd.arg("--jobs").arg(jobs.to_string());
+        }
+        self.features.args(&mut cmd);
+        if let Some(target) = &self.target {
+            cmd.args(["--target", target]);
+        }
+        match &self.profile {
+            Profile::Debug => {}
+            Profile::Release => {
+                cmd.arg("--release");
+            }
+            Profile::Small => {
+                for setting in [
+                    r#"inherits="release""#,
+                    r#"opt-level="z""#,
+                    "lto=true",
+                    "codegen-units=1",
+                    r#"strip="symbols""#,
+                ] {
+                    cmd.arg("--config")
+                        .arg(format!("profile.scriptr-small.{setting}"));
+                }
+                cmd.args(["--profile", "scriptr-small"]);
+            }
+            Profile::Named(name) => {
+                cmd.args(["--profile", name]);
+            }
+        }
+        cmd.args(tool_args);
+        cmd
+    }
+
+    /// A toolchain `cargo` command that resolves into [`Self::lockfile`].
+    fn lockfile_command(&self) -> Command {
+        let mut cmd = self.toolchain.cargo();
+        // A pinned toolchain needs rustup to run beside its file, so that's as neutral as it gets.
+        if let Some(dir) = &self.isolated_dir
+            && matches!(self.toolchain, Toolchain::Override(_))
+        {
+            cmd.current_dir(dir);
+        }
+        if let Some(lockfile) = &self.lockfile {
+            cmd.env("CARGO_RESOLVER_LOCKFILE_PATH", lockfile);
+        }
+        cmd
+    }
+}
+
+/// A cargo command that ran but failed; scriptr exits with the same code.
+#[derive(Debug)]
+struct CargoFailed {
+    command: &'static str,
+    status: ExitStatus,
+    /// The diagnostics printed for the failure.
+    output: String,
+}
+
+impl std::fmt::Display for CargoFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cargo {} failed with status {}",
+            self.command, self.status
+        )
+    }
+}
+
+impl std::error::Error for CargoFailed {}
+
+/// A build skipped because the same sources failed last time; exits with cargo's code from then.
+#[derive(Debug)]
+struct CachedFailure {
+    code: Option<i32>,
+}
+
+impl std::fmt::Display for CachedFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cargo build failed for these sources last time (--cache-failures); use --force to retry"
+        )
+    }
+}
+
+impl std::error::Error for CachedFailure {}
+
+/// Run `cargo update` for the script (its [`manifest_path`]) to re-resolve dependencies (e.g. git
+/// deps to latest commits).
+fn update_deps(script: &Path, settings: &BuildSettings, verbose: bool) -> Result<()> {
+    let mut cmd = settings.update_command(script, verbose);
+    let status = cmd.status().context("failed to run cargo update")?;
+
+    if !status.success() {
+        return Err(CargoFailed {
+            command: "update",
+            status,
+            output: String::new(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Why a script without `fn main` can't be run, for when cargo leaves that unclear.
+const NO_MAIN: &str = "script produced no executable — single-file packages must define `fn main`";
+
+/// Build `script` via Cargo, returning the path to the resulting binary.
+fn rebuild(
+    cmd: Command,
+    script: &Path,
+    toolchain: &Toolchain,
+    report: Reporting,
+    retries: u32,
+) -> Result<PathBuf> {
+    let name = script.file_name().unwrap_or(script.as_os_str());
+    let label = format!("building {}", name.to_string_lossy());
+    let output = run_cargo(cmd, "build", report, retries, Some(&label)).map_err(|err| {
+        // Still cargo's failure either way, so it keeps cargo's exit code.
+        match err.downcast_ref::<CargoFailed>() {
+            // E0601: `main` function not found.
+            Some(failed) if failed.output.contains("E0601") => err.context(NO_MAIN),
+            Some(failed) if lacks_script_support(&failed.output) => {
+                let cargo = toolchain
+                    .preflight(false)
+                    .unwrap_or_else(|_| "its cargo".to_string());
+                err.context(format!(
+                    "toolchain {toolchain} ({cargo}) doesn't support single-file packages \
+                     (`cargo -Zscript`); try a newer nightly with `rustup update nightly`, or \
+                     choose one with --toolchain"
+                ))
+            }
+            _ => err,
+        }
+    })?;
+    if report.show_warnings {
+        for diagnostic in &output.diagnostics {
+            write_diagnostics(diagnostic);
+        }
+    }
+    if report.verbose && output.warnings > 0 {
+        let plural = if output.warnings == 1 { "" } else { "s" };
+        debug!("Build succeeded with {} warning{plural}", output.warnings);
+    }
+    let bin_path = output
+        .executable
+        .ok_or_else(|| anyhow::anyhow!("cargo build succeeded, but {NO_MAIN}"))?;
+    // Checked here rather than left to exec, whose "not found" would send a cache hit's caller
+    // around to build again.
+    if !bin_path.is_file() {
+        anyhow::bail!(
+            "cargo build reported {} as the binary, but there's no such file",
+            bin_path.display()
+        );
+    }
+    if report.verbose
+        && let Ok(metadata) = fs::metadata(&bin_path)
+    {
+        debug!("Binary size: {}", format_bytes(metadata.len()));
+    }
+    Ok(bin_path)
+}
+
+/// How often a [`Progress`] line redraws, and how long a build runs before it first appears.
+const PROGRESS_TICK: Duration = Duration::from_millis(250);
+
+/// A `building foo.rs... 12s` line that a thread of its own keeps redrawing in place on stderr
+/// while cargo builds, naming the crate cargo last finished. Dropping it erases the line.
+struct Progress {
+    compiled: Option<mpsc::Sender<String>>,
+    painter: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Progress {
+    fn start(label: String) -> Self {
+        let (compiled, finished) = mpsc::channel::<String>();
+        let painter = std::thread::spawn(move || {
+            let started = Instant::now();
+            let mut last = None;
+            let mut drawn = false;
+            for frame in ['|', '/', '-', '\\'].into_iter().cycle() {
+                match finished.recv_timeout(PROGRESS_TICK) {
+                    Ok(name) => last = Some(name),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+                if started.elapsed() < PROGRESS_TICK {
+                    continue;
+                }
+                let secs = started.elapsed().as_secs();
+                match &last {
+                    Some(name) => eprint!("\r\x1b[2K{frame} {label}... {secs}s ({name})"),
+                    None => eprint!("\r\x1b[2K{frame} {label}... {secs}s"),
+                }
+                drawn = true;
+            }
+            if drawn {
+                eprint!("\r\x1b[2K");
+            }
+        });
+        Self {
+            compiled: Some(compiled),
+            painter: Some(painter),
+        }
+    }
+
+    /// Note that cargo finished building the crate `name`.
+    fn compiled(&self, name: &str) {
+        if let Some(compiled) = &self.compiled {
+            // The painter only stops once this is dropped.
+            compiled.send(name.to_string()).ok();
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        drop(self.compiled.take());
+        if let Some(painter) = self.painter.take() {
+            painter.join().ok();
+        }
+    }
+}
+
+/// What a successful cargo invocation reported.
+struct CargoOutput {
+    /// The binary it produced, if any (`cargo check` never does).
+    executable: Option<PathBuf>,
+    /// Rendered diagnostics, collected as for a failure but not printed.
+    diagnostics: Vec<String>,
+    /// How many of the compiler's messages were warnings, printed or not.
+    warnings: usize,
+}
+
+/// Run a cargo `command` whose JSON messages go to stdout, failing with [`CargoFailed`].
+///
+/// `cmd` is an invocation from [`BuildSettings`]. With `json`, cargo's messages are forwarded
+/// verbatim to stdout instead of having their rendered diagnostics reprinted on failure; with
+/// `quiet`, only error diagnostics are reprinted. A failure that looks transient (see
+/// [`is_transient_failure`]) is retried up to `retries` times. While cargo runs, a [`Progress`]
+/// line with `label` shows if `report` allows one.
+fn run_cargo(
+    mut cmd: Command,
+    command: &'static str,
+    report: Reporting,
+    retries: u32,
+    label: Option<&str>,
+) -> Result<CargoOutput> {
+    let Reporting {
+        verbose,
+        quiet,
+        json,
+        progress,
+        ..
+    } = report;
+    let mut attempt = 0;
+    loop {
+        // Only the script may read our stdin; cargo gets none, so it can't swallow input meant
+        // for an interactive script (under --watch, say).
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to spawn cargo")?;
+        let stdout = child.stdout.take().expect("piped");
+        let stderr = child.stderr.take().expect("piped");
+        // Drain stderr alongside stdout: left unread until stdout closes, a chatty build would
+        // fill the pipe and block cargo, which would then never close stdout.
+        let stderr = std::thread::spawn(move || {
+            let mut output = String::new();
+            BufReader::new(stderr)
+                .read_to_string(&mut output)
+                .map(|_| output)
+        });
+
+        let progress = label
+            .filter(|_| progress)
+            .map(|label| Progress::start(label.to_string()));
+        // Parse the JSON stream to find the executable path and collect errors.
+        let reader = BufReader::new(stdout);
+        let mut bin_path = None::<PathBuf>;
+        let mut error_messages = Vec::new();
+        let mut warnings = 0;
+        let mut transient = false;
+
+        for line in reader.lines() {
+            let line = line?;
+            if let Ok(val) = serde_json::from_str::<serde_json::Value>(&line) {
+                if json {
+                    println!("{line}");
+                }
+                match val["reason"].as_str() {
+                    Some("compiler-artifact") => {
+                        if let Some(executable) = val["executable"].as_str() {
+                            bin_path = Some(PathBuf::from(executable));
+                        }
+                        // Build scripts are all called `build-script-build`; skip them.
+                        let kinds = val["target"]["kind"].as_array();
+                        let build_script = kinds
+                            .is_some_and(|kinds| kinds.iter().any(|kind| kind == "custom-build"));
+                        if let Some(progress) = &progress
+                            && !build_script
+                            && let Some(name) = val["target"]["name"].as_str()
+                        {
+                            progress.compiled(name);
+                        }
+                    }
+                    Some("compiler-message") => {
+                        let level = val["message"]["level"].as_str().unwrap_or_default();
+                        let is_error = level.starts_with("error");
+                        // Not counting rustc's own "N warnings emitted" summary.
+                        if level == "warning"
+                            && !val["message"]["message"]
+                                .as_str()
+                                .is_some_and(|text| text.ends_with(" emitted"))
+                        {
+                            warnings += 1;
+                        }
+                        let Some(message) = val["message"]["rendered"].as_str() else {
+                            continue;
+                        };
+                        // Linker failures arrive as diagnostics, not on stderr.
+                        transient |= is_error && is_transient_failure(message);
+                        if !json && (is_error || !quiet) {
+                            error_messages.push(message.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Cargo is done once it closes stdout; clear the line before anything else is printed.
+        drop(progress);
+        // Collect stderr in case of failure
+        let stderr_output = stderr.join().expect("stderr reader doesn't panic")?;
+
+        let status = child.wait()?;
+        if !status.success() {
+            if attempt < retries && (transient || is_transient_failure(&stderr_output)) {
+                attempt += 1;
+                if !quiet {
+                    note!("cargo {command} hit a transient error; retrying ({attempt}/{retries})");
+                }
+                std::thread::sleep(Duration::from_millis(250 * u64::from(attempt)));
+                continue;
+            }
+            // Print compilation errors from JSON output
+            let mut output = error_messages.concat();
+            // Also print any stderr output (when quiet, only if it's all there is to go on)
+            if !stderr_output.is_empty() && (!quiet || error_messages.is_empty()) {
+                output.push_str(&stderr_output);
+                output.push('\n');
+            }
+            eprint!("{output}");
+            return Err(CargoFailed {
+                command,
+                status,
+                output,
+            }
+            .into());
+        }
+
+        // Print stderr output in verbose mode even on success
+        if verbose && !stderr_output.is_empty() {
+            write_diagnostics(&format!("{stderr_output}\n"));
+        }
+        return Ok(CargoOutput {
+            executable: bin_path,
+            diagnostics: error_messages,
+            warnings,
+        });
+    }
+}
+
+/// Whether cargo's output points at a failure that an identical rerun could get past: the
+/// executable being busy while the linker writes it, or the OS being briefly out of a resource.
+/// Compile errors never match, so they're not retried.
+fn is_transient_failure(output: &str) -> bool {
+    const PATTERNS: [&str; 4] = [
+        "text file busy",
+        "resource temporarily unavailable",
+        "os error 26", // ETXTBSY
+        "os error 11", // EAGAIN
+    ];
+    let output = output.to_lowercase();
+    PATTERNS.iter().any(|pattern| output.contains(pattern))
+}
+
+/// Whether cargo rejected `-Zscript` itself: a stable or beta cargo refusing `-Z` flags, or a
+/// nightly that doesn't know (or no longer knows) about scripts.
+fn lacks_script_support(output: &str) -> bool {
+    const PATTERNS: [&str; 3] = [
+        "unknown `-Z` flag specified: script",
+        "`-Z` flag is only accepted on the nightly channel",
+        "the manifest-path must be a path to a Cargo.toml file",
+    ];
+    PATTERNS.iter().any(|pattern| output.contains(pattern))
+}
+
+/// Whether cargo failed because it needed the network under `--offline` or `--frozen`.
+fn is_offline_miss(output: &str) -> bool {
+    const PATTERNS: [&str; 3] = [
+        "--offline was specified",
+        "offline mode",
+        "unable to get packages",
+    ];
+    PATTERNS.iter().any(|pattern| output.contains(pattern))
+}
+
+/// What `--version --verbose` reports beyond the version, as `(name, value)` rows: the toolchain a
+/// script at `script` would be built with, its cargo and rustc, the cache root and the host
+/// triple. A tool that can't be run is reported as such rather than failing the whole report.
+fn environment_report(
+    toolchain: Option<String>,
+    script: &Path,
+    cache_dir: Option<PathBuf>,
+) -> Result<Vec<(&'static str, String)>> {
+    let toolchain = Toolchain::resolve(toolchain, script)?;
+    let cargo = toolchain
+        .preflight(false)
+        .unwrap_or_else(|err| format!("unavailable ({err:#})"));
+    let rustc = toolchain
+        .rustc()
+        .arg("-vV")
+        .output()
+        .map_err(anyhow::Error::from)
+        .and_then(|output| {
+            if !output.status.success() {
+                anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        });
+    let (rustc, host) = match rustc {
+        Ok(verbose_version) => {
+            let mut lines = verbose_version.lines();
+            let version = lines.next().unwrap_or_default().to_string();
+            let host = lines
+                .find_map(|line| line.strip_prefix("host: "))
+                .unwrap_or("unknown")
+                .to_string();
+            (version, host)
+        }
+        Err(err) => {
+            let unavailable = format!("unavailable ({err:#})");
+            (unavailable.clone(), unavailable)
+        }
+    };
+    Ok(vec![
+        ("toolchain", toolchain.to_string()),
+        ("cargo", cargo),
+        ("rustc", rustc),
+        (
+            "cache",
+            cache_root(cache_dir).map_or_else(
+                |err| format!("unavailable ({err:#})"),
+                |root| root.display().to_string(),
+            ),
+        ),
+        ("host", host),
+    ])
+}
+
+/// Whether a binary for `triple` can run here: the same architecture and operating system as the
+/// host (a `-musl` target on a glibc host counts). Deliberately conservative, as running a
+/// binary the host can't execute only fails, whereas printing its path is always safe.
+fn runs_on_host(triple: &str) -> bool {
+    let os = match std::env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    };
+    let mut parts = triple.split('-');
+    parts.next() == Some(std::env::consts::ARCH) && parts.any(|part| part == os)
+}
+
+/// Either print `bin` to stdout (for `--print-path`) or hand the process over to it; with a
+/// `time_limit`, run it as a child instead, so that it can be stopped.
+///
+/// When the script itself was read from stdin, that stream is spent, so the binary is given the
+/// controlling terminal instead (where there is one).
+fn launch(
+    bin: &Path,
+    arg0: &OsStr,
+    args: &[OsString],
+    env: &ScriptEnv,
+    print_path: bool,
+    from_stdin: bool,
+    time_limit: Option<Duration>,
+) -> Result<()> {
+    if print_path {
+        println!("{}", bin.display());
+        return Ok(());
+    }
+    let stdin = if from_stdin {
+        terminal_stdin()
+    } else {
+        Stdio::inherit()
+    };
+    let err = match time_limit {
+        Some(limit) => run_with_time_limit(bin, arg0, args, env, stdin, limit),
+        None => exec_image(bin, arg0, args, env, stdin),
+    };
+    Err(err).with_context(|| format!("failed to run {}", bin.display()))
+}
+
+/// Exit code for a script stopped by `--time-limit`, the same as timeout(1)'s.
+const EXIT_TIMED_OUT: i32 = 124;
+
+/// How long a script stopped by `--time-limit` gets to exit after SIGTERM before it's killed.
+const TIME_LIMIT_GRACE: Duration = Duration::from_secs(2);
+
+/// Run `bin` for at most `limit`, then exit: with its exit code, or [`EXIT_TIMED_OUT`] if it had
+/// to be stopped. Returns only if it can't be started.
+///
+/// A script out of time gets SIGTERM, then SIGKILL if it's still there [`TIME_LIMIT_GRACE`]
+/// later. SIGINT and SIGTERM sent to scriptr meanwhile are passed on, as with `--watch`.
+fn run_with_time_limit(
+    bin: &Path,
+    arg0: &OsStr,
+    args: &[OsString],
+    env: &ScriptEnv,
+    stdin: Stdio,
+    limit: Duration,
+) -> std::io::Error {
+    // Without the handlers, a signal would kill scriptr and leave the script running untimed.
+    if let Err(err) = forward_signals() {
+        return std::io::Error::other(format!("{err:#}"));
+    }
+    let mut child = match script_command(bin, arg0, args, env, stdin).spawn() {
+        Ok(child) => child,
+        Err(err) => return err,
+    };
+    forward_signals_to(Some(&child));
+    let deadline = Instant::now() + limit;
+    let mut terminated = None::<Instant>;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {}
+            Err(err) => return err,
+        }
+        let now = Instant::now();
+        match terminated {
+            None if now >= deadline => {
+                terminate(&mut child);
+                terminated = Some(now);
+            }
+            Some(at) if now >= at + TIME_LIMIT_GRACE => {
+                child.kill().ok();
+            }
+            _ => {}
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    forward_signals_to(None);
+    if terminated.is_some() {
+        note!(
+            "Script exceeded its {}s time limit; stopped it",
+            limit.as_secs()
+        );
+        std::process::exit(EXIT_TIMED_OUT);
+    }
+    if let Some(signal) = received_signal() {
+        std::process::exit(128 + signal);
+    }
+    std::process::exit(exit_code(status))
+}
+
+/// Ask `child` to exit: SIGTERM, where there is such a thing.
+#[cfg(unix)]
+fn terminate(child: &mut Child) {
+    use nix::{
+        sys::signal::{Signal, kill},
+        unistd::Pid,
+    };
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).ok();
+}
+
+#[cfg(windows)]
+fn terminate(child: &mut Child) {
+    child.kill().ok();
+}
+
+/// The code to exit with for a child that exited with `status`: its own, or 128 + the signal that
+/// killed it, as a shell reports it.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Copy `bin` into `dir` for `--install`, named after `script` (`foo.rs` becomes `foo`), and
+/// return where it went. An existing file there is only replaced with `force`.
+///
+/// The copy is staged beside the destination and renamed over it, so a binary that's running
+/// can be replaced, and a reader never sees half of one.
+fn install_to(bin: &Path, dir: &Path, script: &Path, force: bool) -> Result<PathBuf> {
+    let mut name = script_name(script)
+        .context("script has no file name")?
+        .to_owned();
+    if let Some(ext) = bin.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    let dest = dir.join(&name);
+    if !force && fs::symlink_metadata(&dest).is_ok() {
+        anyhow::bail!(
+            "{} already exists; pass --force to overwrite it",
+            dest.display()
+        );
+    }
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let mut staged = OsString::from(".");
+    staged.push(&name);
+    staged.push(format!(".{}.tmp", std::process::id()));
+    let staged = dir.join(staged);
+    // `fs::copy` carries the permission bits over, executable one included.
+    fs::copy(bin, &staged).with_context(|| format!("failed to copy {}", bin.display()))?;
+    if let Err(err) = fs::rename(&staged, &dest) {
+        let _ = fs::remove_file(&staged);
+        return Err(err).with_context(|| format!("failed to install {}", dest.display()));
+    }
+    Ok(dest)
+}
+
+/// Build each of `jobs` for `--build-all`, without running any, then print a summary table.
+///
+/// A script that fails to build doesn't stop the rest; the first failure is returned once every
+/// script has been tried.
+fn build_each(jobs: Vec<(PathBuf, Result<Job>)>, force: bool, update: bool) -> Result<()> {
+    let mut rows = Vec::new();
+    let mut first_err = None;
+    for (script, job) in jobs {
+        let status = match job.and_then(|job| job.prepare(force, update)) {
+            Ok(Prepared {
+                cache_hit: true, ..
+            }) => "cached",
+            Ok(_) => "built",
+            Err(err) => {
+                eprintln!("{}: {err:#}", script.display());
+                first_err.get_or_insert(err);
+                "failed"
+            }
+        };
+        rows.push((status, script));
+    }
+
+    for (status, script) in &rows {
+        println!("{status:<6}  {}", script.display());
+    }
+    let count = |wanted| rows.iter().filter(|(status, _)| *status == wanted).count();
+    let failed = count("failed");
+    let noun = if rows.len() == 1 { "script" } else { "scripts" };
+    println!(
+        "{} {noun}: {} built, {} cached, {failed} failed",
+        rows.len(),
+        count("built"),
+        count("cached")
+    );
+    match first_err {
+        Some(err) => Err(err.context(format!(
+            "{failed} of {} scripts failed to build",
+            rows.len()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Build and run `job` as a child process, restarting it whenever the script changes.
+///
+/// `exec()` can't be used since we need to outlive each run. Changes are detected by polling the
+/// script's mtime every `interval`; a change while the child is still running kills it first.
+/// Build failures are reported and then waited out like any other run. SIGINT or SIGTERM ends
+/// the loop: the child gets the signal too (see [`forward_signals`]), and once it has exited we
+/// exit with 128 + the signal's number.
+fn watch_loop(
+    job: &Job,
+    arg0: &OsStr,
+    args: &[OsString],
+    env: &ScriptEnv,
+    mut force: bool,
+    mut update: bool,
+    interval: Duration,
+) -> Result<()> {
+    forward_signals()?;
+    loop {
+        let seen = mtime_nanos(&job.script)?;
+        let prepared = job.prepare(force, update);
+        if let Some(signal) = received_signal() {
+            std::process::exit(128 + signal);
+        }
+        let mut child = match prepared {
+            // The script keeps the terminal between rebuilds: cargo never reads stdin (see
+            // `run_cargo`), so input typed during a build waits for the next run.
+            Ok(Prepared { bin, .. }) => Some(
+                script_command(&bin, arg0, args, env, Stdio::inherit())
+                    .spawn()
+                    .with_context(|| format!("failed to run {}", bin.display()))?,
+            ),
+            Err(err) => {
+                eprintln!("{err:#}");
+                None
+            }
+        };
+        forward_signals_to(child.as_ref());
+        // Only the first build honors --force/--update; later ones are driven by edits.
+        force = false;
+        update = false;
+
+        loop {
+            std::thread::sleep(interval);
+            if let Some(signal) = received_signal() {
+                if let Some(mut running) = child {
+                    running.wait()?;
+                }
+                std::process::exit(128 + signal);
+            }
+            if let Some(running) = &mut child
+                && let Some(status) = running.try_wait()?
+            {
+                forward_signals_to(None);
+                if !job.report.quiet {
+                    note!("Script exited ({status}); waiting for changes...");
+                }
+                child = None;
+            }
+            // A transient error (e.g. an editor mid-save) just means "look again later".
+            if mtime_nanos(&job.script).is_ok_and(|mtime| mtime != seen) {
+                break;
+            }
+        }
+
+        if let Some(mut running) = child {
+            running.kill().ok();
+            running.wait()?;
+            forward_signals_to(None);
+        }
+        if !job.report.quiet {
+            note!("Change detected, rebuilding...");
+        }
+    }
+}
+
+/// The pid of the script child that SIGINT/SIGTERM are forwarded to, or 0 for none.
+static FORWARD_TO: AtomicU32 = AtomicU32::new(0);
+
+/// The last SIGINT/SIGTERM scriptr received while forwarding, or 0 for none.
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+/// Catch SIGINT and SIGTERM from now on, passing them to the child set by [`forward_signals_to`]
+/// and recording them for [`received_signal`], so that code waiting on a child can stop it and
+/// then exit with 128 + the signal's number.
+///
+/// The child stays in our process group so that it keeps the terminal, so it is signalled on its
+/// own rather than by group (which would signal us too). That also means a Ctrl-C typed at the
+/// terminal has already reached it, so only signals sent by another process are passed on.
+#[cfg(unix)]
+fn forward_signals() -> Result<()> {
+    use nix::{
+        sys::signal::{Signal, kill},
+        unistd::Pid,
+    };
+    use signal_hook::{
+        consts::{SIGINT, SIGTERM},
+        iterator::{SignalsInfo, exfiltrator::WithOrigin},
+        low_level::siginfo::Cause,
+    };
+
+    static INSTALLED: OnceLock<()> = OnceLock::new();
+    if INSTALLED.get().is_some() {
+        return Ok(());
+    }
+    let mut signals = SignalsInfo::<WithOrigin>::new([SIGINT, SIGTERM])
+        .context("failed to install signal handlers")?;
+    std::thread::spawn(move || {
+        for origin in signals.forever() {
+            RECEIVED.store(origin.signal, Ordering::SeqCst);
+            let pid = FORWARD_TO.load(Ordering::SeqCst);
+            if pid != 0
+                && origin.cause != Cause::Kernel
+                && let Ok(signal) = Signal::try_from(origin.signal)
+            {
+                kill(Pid::from_raw(pid as i32), signal).ok();
+            }
+        }
+    });
+    INSTALLED.set(()).ok();
+    Ok(())
+}
+
+/// Windows delivers Ctrl-C to every process on the console, so there's nothing to forward.
+#[cfg(windows)]
+fn forward_signals() -> Result<()> {
+    Ok(())
+}
+
+/// Make `child` the target of [`forward_signals`], or clear it once the child has been reaped.
+fn forward_signals_to(child: Option<&Child>) {
+    FORWARD_TO.store(child.map_or(0, Child::id), Ordering::SeqCst);
+}
+
+/// The signal caught by [`forward_signals`], if any.
+fn received_signal() -> Option<i32> {
+    Some(RECEIVED.load(Ordering::SeqCst)).filter(|&signal| signal != 0)
+}
+
+/// What a script run as `script` sees as its argv[0] by default: the file stem, as in `tool` for
+/// `./tool.rs`. Made UTF-8 if it isn't, since `std::env::args` panics on anything else.
+fn script_arg0(script: &Path) -> OsString {
+    let stem = script_name(script).unwrap_or(script.as_os_str());
+    stem.to_string_lossy().into_owned().into()
+}
+
+/// The controlling terminal as a stdin handle, falling back to our own stdin.
+fn terminal_stdin() -> Stdio {
+    #[cfg(unix)]
+    if let Ok(tty) = File::open("/dev/tty") {
+        return tty.into();
+    }
+    Stdio::inherit()
+}
+
+/// What a script runs with besides its arguments.
+#[derive(Default)]
+struct ScriptEnv {
+    /// Set with `--env` (or the companion file's `env`).
+    vars: Vec<(String, String)>,
+    /// `--run-in`, absolute; the caller's own working directory when `None`.
+    dir: Option<PathBuf>,
+}
+
+/// The command that runs a built script. Every way of running one goes through here, so the
+/// script always gets our stdout and stderr, and `stdin`: normally our own stdin too, so that
+/// prompts and raw-mode terminal programs behave as if run directly. It sees `arg0` as its
+/// argv[0] rather than the cached binary's path, except on Windows, where that can't be changed.
+fn script_command(
+    bin: &Path,
+    arg0: &OsStr,
+    args: &[OsString],
+    env: &ScriptEnv,
+    stdin: Stdio,
+) -> Command {
+    let mut cmd = match &env.dir {
+        // A relative path (from a relative cache dir) would be looked up from `dir` instead.
+        Some(dir) => {
+            let mut cmd = Command::new(std::path::absolute(bin).unwrap_or_else(|_| bin.into()));
+            cmd.current_dir(dir);
+            cmd
+        }
+        None => Command::new(bin),
+    };
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::arg0(&mut cmd, arg0);
+    #[cfg(not(unix))]
+    let _ = arg0;
+    cmd.args(args)
+        .envs(env.vars.iter().cloned())
+        .stdin(stdin)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+    cmd
+}
+
+/// Replace the current process image with `bin`, passing through `args`. Only returns if that
+/// fails, with the reason.
+///
+/// exec(2) keeps our file descriptors, so the script inherits the terminal (or whatever stdio
+/// we were given) exactly as a directly run program would.
+#[cfg(unix)]
+fn exec_image(
+    bin: &Path,
+    arg0: &OsStr,
+    args: &[OsString],
+    env: &ScriptEnv,
+    stdin: Stdio,
+) -> std::io::Error {
+    use std::os::unix::process::CommandExt;
+
+    script_command(bin, arg0, args, env, stdin).exec()
+}
+
+/// Run `bin` to completion with `args` and exit with its exit code.
+///
+/// Windows has no exec(2), so the closest equivalent is to spawn the binary with inherited
+/// stdio, wait for it, and forward its exit code as our own. Returns only if it can't be started.
+#[cfg(windows)]
+fn exec_image(
+    bin: &Path,
+    arg0: &OsStr,
+    args: &[OsString],
+    env: &ScriptEnv,
+    stdin: Stdio,
+) -> std::io::Error {
+    let status = script_command(bin, arg0, args, env, stdin).status();
+    match status {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(err) => err,
+    }
+}
+
+/// A shebang like `#!/usr/local/bin/scriptr -d -v` reaches us as `scriptr "-d -v" /abs/script.rs
+/// args...`: the kernel passes everything after the interpreter as one argument. Split that
+/// argument on whitespace, as `env -S` would, so it parses like the options it spells.
+fn expand_shebang_options(mut all_args: Vec<OsString>) -> Vec<OsString> {
+    let bundle = all_args
+        .get(1)
+        .and_then(|arg| arg.to_str())
+        .filter(|arg| arg.starts_with('-') && arg.contains(char::is_whitespace))
+        .map(str::to_string);
+    if let Some(bundle) = bundle {
+        all_args.remove(1);
+        all_args.splice(1..1, bundle.split_whitespace().map(OsString::from));
+    }
+    all_args
+}
+
+/// Run `f`, reporting how long it took as `phase` when verbose. Otherwise the clock isn't read.
+fn timed<T>(verbose: bool, phase: &str, f: impl FnOnce() -> T) -> T {
+    if !verbose {
+        return f();
+    }
+    let start = Instant::now();
+    let out = f();
+    log(
+        Level::Debug,
+        Some(phase),
+        format_args!(
+            "phase {phase} took {:.2}ms",
+            start.elapsed().as_secs_f64() * 1e3
+        ),
+    );
+    out
+}
+
+/// Return `(script_index, passthrough_args)` where `script_index` is the index in `all_args`
+/// for the script path argument, if one is found. With `--eval` there's no script path, so it's
+/// the index of the last argument for scriptr, and everything after goes to the snippet.
+///
+/// Only the first `--` is a separator, whether it comes before the script path or straight
+/// after it (`scriptr foo.rs -- a -- b` passes `a -- b`); any others are the script's.
+fn split_invocation_args(all_args: &[impl AsRef<OsStr>]) -> (Option<usize>, Vec<OsString>) {
+    let value_flags = ValueFlags::from_clap();
+    let mut script_index = None;
+    let mut eval = false;
+    let mut separated = false;
+    let mut i = 1;
+
+    while i < all_args.len() {
+        // Options are all UTF-8, so anything else can only be the script path.
+        let Some(arg) = all_args[i].as_ref().to_str() else {
+            script_index = Some(i);
+            break;
+        };
+        if arg == "--" {
+            // `--` separates scriptr args from script invocation.
+            separated = true;
+            if eval {
+                script_index = Some(i);
+            } else if i + 1 < all_args.len() {
+                script_index = Some(i + 1);
+            }
+            break;
+        }
+
+        eval |= arg == "--eval" || arg.starts_with("--eval=") || is_short_eval(arg, &value_flags);
+        if takes_value(arg, &value_flags) {
+            i += 2;
+            continue;
+        }
+
+        // A lone `-` is the stdin script, not an option.
+        if arg == "-" || !arg.starts_with('-') {
+            script_index = Some(if eval { i - 1 } else { i });
+            break;
+        }
+
+        i += 1;
+    }
+
+    let passthrough_args = script_index
+        .map(|idx| {
+            let after_path = !eval && !separated;
+            let skip = if after_path && all_args.get(idx + 1).is_some_and(|a| a.as_ref() == "--") {
+                2
+            } else {
+                1
+            };
+            all_args[(idx + skip)..]
+                .iter()
+                .map(|arg| arg.as_ref().to_os_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    (script_index, passthrough_args)
+}
+
+/// The options that consume a following value, read from the clap definition so they can't drift.
+struct ValueFlags {
+    long: Vec<String>,
+    short: Vec<char>,
+}
+
+impl ValueFlags {
+    fn from_clap() -> Self {
+        let mut flags = Self {
+            long: Vec::new(),
+            short: Vec::new(),
+        };
+        for arg in Opts::command().get_arguments() {
+            if arg.is_positional() || !arg.get_action().takes_values() {
+                continue;
+            }
+            flags.long.extend(arg.get_long().map(|l| format!("--{l}")));
+            flags.short.extend(arg.get_short());
+        }
+        flags
+    }
+}
+
+/// Whether `arg` is an option whose value is the *next* argument (`--id X`, `-x X`, `-vx X`),
+/// as opposed to one carrying its value inline (`--id=X`, `-xX`).
+fn takes_value(arg: &str, flags: &ValueFlags) -> bool {
+    if arg.starts_with("--") {
+        return flags.long.iter().any(|l| l == arg);
+    }
+    let Some(cluster) = arg.strip_prefix('-') else {
+        return false;
+    };
+    // In a short cluster, the first value-taking flag swallows the rest as its value.
+    match cluster
+        .char_indices()
+        .find(|(_, c)| flags.short.contains(c))
+    {
+        Some((idx, c)) => idx + c.len_utf8() == cluster.len(),
+        None => false,
+    }
+}
+
+/// Whether `arg` is a cluster of short flags ending in `-e`, or with `-e`'s value attached.
+fn is_short_eval(arg: &str, flags: &ValueFlags) -> bool {
+    let Some(cluster) = arg.strip_prefix('-').filter(|c| !c.starts_with('-')) else {
+        return false;
+    };
+    cluster.chars().find(|c| flags.short.contains(c)) == Some('e')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_with_id_value_does_not_consume_script_path() {
+        let args = vec![
+            "scriptr".to_string(),
+            "--id".to_string(),
+            "123e4567-e89b-12d3-a456-426614174000".to_string(),
+            "/tmp/script.rs".to_string(),
+            "--flag".to_string(),
+        ];
+        let (script_idx, passthrough) = split_invocation_args(&args);
+        assert_eq!(script_idx, Some(3));
+        assert_eq!(passthrough, vec!["--flag"]);
+    }
+
+    #[test]
+    fn split_with_eval_passes_everything_after_it_to_the_snippet() {
+        let split = |args: &[&str]| split_invocation_args(args);
+        assert_eq!(
+            split(&["scriptr", "--eval", "fn main() {}", "--", "-v", "x"]),
+            (Some(3), vec!["-v".into(), "x".into()])
+        );
+        assert_eq!(
+            split(&["scriptr", "-de", "fn main() {}", "x"]),
+            (Some(2), vec!["x".into()])
+        );
+        assert_eq!(split(&["scriptr", "--eval=1", "--wrap"]), (None, vec![]));
+    }
+
+    #[test]
+    fn split_consumes_only_the_first_separator() {
+        let split = |args: &[&str]| split_invocation_args(args).1;
+        assert_eq!(
+            split(&["scriptr", "foo.rs", "--", "a", "--", "b"]),
+            ["a", "--", "b"]
+        );
+        assert_eq!(
+            split(&["scriptr", "-v", "--", "foo.rs", "--", "a"]),
+            ["--", "a"]
+        );
+        assert_eq!(split(&["scriptr", "foo.rs", "a", "--"]), ["a", "--"]);
+        assert_eq!(split(&["scriptr", "-e", "()", "--", "--"]), ["--"]);
+    }
+
+    #[test]
+    fn split_with_id_equals_works() {
+        let args = vec![
+            "scriptr".to_string(),
+            "--id=abc".to_string(),
+            "/tmp/script.rs".to_string(),
+            "arg1".to_string(),
+        ];
+        let (script_idx, passthrough) = split_invocation_args(&args);
+        assert_eq!(script_idx, Some(2));
+        assert_eq!(passthrough, vec!["arg1"]);
+    }
+
+    #[test]
+    fn split_skips_toolchain_value() {
+        let args = vec![
+            "scriptr".to_string(),
+            "-v".to_string(),
+            "--toolchain".to_string(),
+            "stable".to_string(),
+            "/tmp/script.rs".to_string(),
+        ];
+        let (script_idx, passthrough) = split_invocation_args(&args);
+        assert_eq!(script_idx, Some(4));
+        assert!(passthrough.is_empty());
+    }
+
+    #[test]
+    fn shebang_option_bundle_is_split() {
+        let args = [
+            "scriptr",
+            "-d --toolchain stable",
+            "/tmp/script.rs",
+            "-v",
+            "two words",
+        ];
+        let args = expand_shebang_options(args.map(OsString::from).to_vec());
+        assert_eq!(
+            args,
+            [
+                "scriptr",
+                "-d",
+                "--toolchain",
+                "stable",
+                "/tmp/script.rs",
+                "-v",
+                "two words"
+            ]
+        );
+        let (script_idx, passthrough) = split_invocation_args(&args);
+        assert_eq!(script_idx, Some(4));
+        assert_eq!(passthrough, vec!["-v", "two words"]);
+    }
+
+    #[test]
+    fn toolchain_flag_beats_toolchain_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("rust-toolchain.toml"),
+            "[toolchain]\nchannel = \"nightly-2025-01-01\"\n",
+        )
+        .unwrap();
+        let script = dir.path().join("nested").join("script.rs");
+
+        let pinned = Toolchain::resolve(None, &script).unwrap();
+        assert!(matches!(pinned, Toolchain::Pinned { .. }), "{pinned:?}");
+        assert!(pinned.cache_id().contains("nightly-2025-01-01"));
+
+        let explicit = Toolchain::resolve(Some("stable".into()), &script).unwrap();
+        assert_eq!(explicit.cache_id(), "+stable");
+    }
+
+    #[test]
+    fn read_meta_rejects_garbage_and_missing_bin() {
+        let dir = tempfile::tempdir().unwrap();
+        let meta = dir.path().join("meta.json");
+
+        std::fs::write(&meta, b"\x00{not json").unwrap();
+        assert!(read_meta(&meta).is_err());
+
+        let hash = "0".repeat(64);
+        std::fs::write(
+            &meta,
+            format!(r#"{{"fp":{{"mtime_ns":1,"hash":"{hash}"}},"bin":""}}"#),
+        )
+        .unwrap();
+        assert!(read_meta(&meta).is_err());
+
+        std::fs::write(
+            &meta,
+            format!(r#"{{"fp":{{"mtime_ns":1,"hash":"{hash}"}},"bin":"/x"}}"#),
+        )
+        .unwrap();
+        assert!(read_meta(&meta).is_ok());
+    }
+
+    #[test]
+    fn binaries_in_the_cache_are_recorded_relative_to_it() {
+        let old = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        let meta_path = old.path().join("entry.json");
+        fs::create_dir(old.path().join("bin")).unwrap();
+        fs::write(old.path().join("bin/entry-1"), "").unwrap();
+
+        write_meta(&meta_path, &fake_meta(old.path().join("bin/entry-1"))).unwrap();
+        let stored = fs::read_to_string(&meta_path).unwrap();
+        assert!(stored.contains(r#""bin":"bin/entry-1""#), "{stored}");
+        let meta = only_variant(read_meta(&meta_path).unwrap());
+        assert_eq!(meta.bin, old.path().join("bin/entry-1"));
+
+        // An older entry's absolute path still finds the binary once the cache has moved.
+        let absolute = format!(
+            r#"{{"fp":{{"mtime_ns":1,"hash":"{}"}},"bin":"{}"}}"#,
+            "0".repeat(64),
+            old.path().join("bin/entry-1").display()
+        );
+        fs::write(&meta_path, absolute).unwrap();
+        let moved = new.path().join("cache");
+        fs::rename(old.path(), &moved).unwrap();
+        let meta = only_variant(read_meta(&moved.join("entry.json")).unwrap());
+        assert_eq!(meta.bin, moved.join("bin/entry-1"));
+    }
+
+    #[test]
+    fn concurrent_metadata_writes_leave_valid_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let meta_path = dir.path().join("racy.json");
+        std::thread::scope(|scope| {
+            for writer in 0..8 {
+                let meta_path = &meta_path;
+                scope.spawn(move || {
+                    for round in 0..25 {
+                        let meta = fake_meta(PathBuf::from(format!("/bin/{writer}-{round}")));
+                        write_meta(meta_path, &meta).unwrap();
+                    }
+                });
+            }
+        });
+
+        let meta = only_variant(read_meta(&meta_path).unwrap());
+        assert!(meta.bin.starts_with("/bin"));
+        // Every writer cleaned up after itself.
+        let leftovers: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .filter(|name| name != "racy.json")
+            .collect();
+        assert!(leftovers.is_empty(), "leftovers: {leftovers:?}");
+    }
+
+    #[test]
+    fn format_bytes_picks_units() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn parse_size_accepts_suffixes() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("512K"), Ok(512 << 10));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert!(parse_size("12Q").is_err());
+    }
+
+    #[test]
+    fn features_normalize_like_cargo_reads_them() {
+        let id = |lists: &[&str], all, no_default| {
+            let lists: Vec<String> = lists.iter().map(|l| l.to_string()).collect();
+            Features::new(&lists, all, no_default).map(|f| f.id())
+        };
+        assert_eq!(id(&[], false, false).unwrap(), "");
+        assert_eq!(
+            id(&["color,cli", "cli serde/derive"], false, true).unwrap(),
+            "no-default-features,+cli,+color,+serde/derive"
+        );
+        // --all-features overrides the rest, as it does for cargo.
+        assert_eq!(id(&["cli"], true, true).unwrap(), "all-features");
+        assert!(id(&["bad name!"], false, false).is_err());
+        assert!(id(&["a/b/c"], false, false).is_err());
+    }
+
+    #[test]
+    fn parse_duration_accepts_units() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("24h"), Ok(Duration::from_secs(24 * 60 * 60)));
+        assert_eq!(
+            parse_duration("7d"),
+            Ok(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        assert!(parse_duration("3y").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+
+    /// Write a cache entry with a `bin_size`-byte binary, last used `age_secs` ago.
+    fn fake_entry(root: &Path, name: &str, bin_size: usize, age_secs: u64) -> std::path::PathBuf {
+        let bin = root.join(format!("{name}.bin"));
+        fs::write(&bin, vec![0u8; bin_size]).unwrap();
+        let meta_path = root.join(format!("{name}.json"));
+        write_meta(&meta_path, &fake_meta(bin)).unwrap();
+        File::options()
+            .write(true)
+            .open(&meta_path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(age_secs))
+            .unwrap();
+        meta_path
+    }
+
+    /// Metadata for a release build of an empty script, cached at `bin`.
+    fn fake_meta(bin: PathBuf) -> Meta {
+        single_build(
+            Fingerprint {
+                mtime_ns: 0,
+                hash: "0".repeat(64),
+                hash_algo: "blake3".into(),
+                modules: Vec::new(),
+                profile: "release".into(),
+                toolchain: "+nightly".into(),
+                cargo_args: Vec::new(),
+                target: String::new(),
+                rustflags: String::new(),
+                offline: false,
+                frozen: false,
+                isolated: false,
+                features: String::new(),
+                registry: String::new(),
+                edition: String::new(),
+            },
+            bin,
+        )
+    }
+
+    /// Metadata holding just the one build, of `fp` at `bin`.
+    fn single_build(fp: Fingerprint, bin: PathBuf) -> Meta {
+        Meta {
+            script: None,
+            variants: BTreeMap::from([(
+                fp.variant(),
+                Variant {
+                    fp,
+                    bin,
+                    builder_version: None,
+                    built_at: None,
+                    bin_hash: None,
+                    source_bytes: None,
+                    build_ms: None,
+                    bin_bytes: None,
+                },
+            )]),
+        }
+    }
+
+    /// The build in metadata that should hold exactly one.
+    fn only_variant(meta: Meta) -> Variant {
+        let mut variants = meta.variants.into_values();
+        let variant = variants.next().expect("one build");
+        assert!(variants.next().is_none(), "more than one build");
+        variant
+    }
+
+    /// A nightly release build with nothing else set, for tests to override what they need.
+    fn test_settings() -> BuildSettings {
+        BuildSettings {
+            toolchain: Toolchain::Override("nightly".into()),
+            profile: Profile::Release,
+            cargo_args: Vec::new(),
+            target: None,
+            rustflags: String::new(),
+            lockfile: None,
+            locked: false,
+            offline: false,
+            frozen: false,
+            isolated_dir: None,
+            jobs: None,
+            features: Features::default(),
+            registry: String::new(),
+            edition: None,
+        }
+    }
+
+    #[test]
+    fn only_matching_arch_and_os_run_on_host() {
+        let os = match std::env::consts::OS {
+            "macos" => "apple-darwin",
+            "linux" => "unknown-linux-gnu",
+            "windows" => "pc-windows-msvc",
+            os => os,
+        };
+        let arch = std::env::consts::ARCH;
+        assert!(runs_on_host(&format!("{arch}-{os}")));
+        assert!(!runs_on_host(&format!("bogus-{os}")));
+        assert!(!runs_on_host(&format!("{arch}-unknown-none")));
+        assert!(!runs_on_host("wasm32-unknown-unknown"));
+    }
+
+    #[test]
+    fn only_linker_races_count_as_transient() {
+        assert!(is_transient_failure(
+            "error: linking with `cc` failed\n  = note: /usr/bin/ld: cannot open output file: Text file busy"
+        ));
+        assert!(is_transient_failure(
+            "error: could not exec the linker: Resource temporarily unavailable (os error 11)"
+        ));
+        assert!(!is_transient_failure(
+            "error[E0308]: mismatched types\n  expected `u32`, found `&str`"
+        ));
+    }
+
+    #[test]
+    fn dry_run_commands_paste_into_a_shell() {
+        assert_eq!(shell_quote(OsStr::new("--features=a,b")), "--features=a,b");
+        assert_eq!(shell_quote(OsStr::new("")), "''");
+        assert_eq!(shell_quote(OsStr::new("it's here")), r"'it'\''s here'");
+        let mut cmd = Command::new("cargo");
+        cmd.current_dir("/my dir")
+            .env("RUSTFLAGS", "-C opt-level=1")
+            .args(["build", "$HOME"]);
+        assert_eq!(
+            render_command(&cmd),
+            "cd '/my dir' && RUSTFLAGS='-C opt-level=1' cargo build '$HOME'"
+        );
+    }
+
+    #[test]
+    fn rustflags_flag_follows_the_environment() {
+        assert_eq!(effective_rustflags(None, None), "");
+        assert_eq!(
+            effective_rustflags(Some(" -Dwarnings ".into()), None),
+            "-Dwarnings"
+        );
+        assert_eq!(
+            effective_rustflags(Some("-Copt-level=1".into()), Some("-Copt-level=3".into())),
+            "-Copt-level=1 -Copt-level=3"
+        );
+        assert_eq!(
+            effective_rustflags(Some(String::new()), Some("-g".into())),
+            "-g"
+        );
+    }
+
+    #[test]
+    fn builtin_profile_names_map_to_scriptrs_own() {
+        assert_eq!(parse_profile("dev"), Ok(Profile::Debug));
+        assert_eq!(parse_profile("release"), Ok(Profile::Release));
+        assert_eq!(
+            parse_profile("bench-fast"),
+            Ok(Profile::Named("bench-fast".into()))
+        );
+        assert!(parse_profile("").is_err());
+    }
+
+    #[test]
+    fn parse_env_splits_at_the_first_equals() {
+        assert_eq!(parse_env("FOO=bar"), Ok(("FOO".into(), "bar".into())));
+        assert_eq!(parse_env("URL=a=b=c"), Ok(("URL".into(), "a=b=c".into())));
+        assert_eq!(parse_env("EMPTY="), Ok(("EMPTY".into(), String::new())));
+        assert!(parse_env("NOEQUALS").is_err());
+        assert!(parse_env("=value").is_err());
+    }
+
+    #[test]
+    fn evict_lru_removes_oldest_but_never_current() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let oldest = fake_entry(root, "oldest", 10_000, 300);
+        let current = fake_entry(root, "current", 10_000, 200);
+        let newest = fake_entry(root, "newest", 10_000, 100);
+
+        let evicted = evict_lru(root, &current, 25_000).unwrap();
+        assert_eq!(evicted, vec![oldest.clone()]);
+        assert!(!oldest.exists() && !root.join("oldest.bin").exists());
+        assert!(current.exists() && newest.exists());
+
+        // Even when the current entry alone is over budget, it survives.
+        evict_lru(root, &current, 0).unwrap();
+        assert!(current.exists() && !newest.exists());
+    }
+
+    /// The metadata files `removals` names, by file name.
+    fn removed_names(removals: &[Removal]) -> Vec<String> {
+        removals
+            .iter()
+            .map(|r| {
+                r.meta_path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gc_older_than_picks_entries_unused_that_long() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fake_entry(root, "stale", 10_000, 3 * 86_400);
+        fake_entry(root, "fresh", 10_000, 60);
+        let policy = GcPolicy {
+            older_than: Some(Duration::from_secs(86_400)),
+            ..GcPolicy::default()
+        };
+
+        let removals = plan_removals(root, &policy, None).unwrap();
+        assert_eq!(removed_names(&removals), ["stale.json"]);
+        assert_eq!(removals[0].reason, "unused for 3d");
+        assert!(removals[0].bytes > 10_000);
+        // Planning alone removes nothing.
+        assert!(root.join("stale.json").exists() && root.join("stale.bin").exists());
+        removals[0].apply(root).unwrap();
+        assert!(!root.join("stale.json").exists() && !root.join("stale.bin").exists());
+        assert!(root.join("fresh.json").exists());
+    }
+
+    #[test]
+    fn gc_max_size_trims_least_recently_used_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fake_entry(root, "oldest", 10_000, 300);
+        fake_entry(root, "middle", 10_000, 200);
+        fake_entry(root, "newest", 10_000, 100);
+        let policy = GcPolicy {
+            max_size: Some(15_000),
+            ..GcPolicy::default()
+        };
+
+        let removals = plan_removals(root, &policy, None).unwrap();
+        assert_eq!(removed_names(&removals), ["oldest.json", "middle.json"]);
+        assert!(
+            removals[0].reason.starts_with("over the "),
+            "{}",
+            removals[0].reason
+        );
+    }
+
+    #[test]
+    fn gc_orphans_picks_entries_missing_their_script_or_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let no_script = fake_entry(root, "no-script", 100, 0);
+        let mut meta = read_meta(&no_script).unwrap();
+        meta.script = Some(root.join("deleted.rs"));
+        write_meta(&no_script, &meta).unwrap();
+        fake_entry(root, "no-binary", 100, 0);
+        fs::remove_file(root.join("no-binary.bin")).unwrap();
+        let healthy = fake_entry(root, "healthy", 100, 0);
+        let mut meta = read_meta(&healthy).unwrap();
+        meta.script = Some(healthy.clone());
+        write_meta(&healthy, &meta).unwrap();
+        let policy = GcPolicy {
+            orphans: true,
+            ..GcPolicy::default()
+        };
+
+        let removals = plan_removals(root, &policy, None).unwrap();
+        let mut reasons: Vec<_> = removals
+            .iter()
+            .map(|r| (r.meta_path.clone(), r.reason.as_str()))
+            .collect();
+        reasons.sort();
+        assert_eq!(
+            reasons,
+            [
+                (root.join("no-binary.json"), "binary missing"),
+                (no_script, "script missing"),
+            ]
+        );
+    }
+
+    #[test]
+    fn evict_lru_keeps_a_binary_until_its_last_entry_goes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let older = fake_entry(root, "older", 10_000, 300);
+        let newer = fake_entry(root, "newer", 10_000, 200);
+        let current = fake_entry(root, "current", 10_000, 100);
+        // Point `newer` at `older`'s binary, as --dedup does for identical scripts.
+        let variant = only_variant(read_meta(&newer).unwrap());
+        fs::remove_file(&variant.bin).unwrap();
+        write_meta(&newer, &single_build(variant.fp, root.join("older.bin"))).unwrap();
+        File::options()
+            .write(true)
+            .open(&newer)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(200))
+            .unwrap();
+
+        // Dropping `older` frees no space while `newer` still needs the binary.
+        let evicted = evict_lru(root, &current, 15_000).unwrap();
+        assert_eq!(evicted, vec![older.clone(), newer.clone()]);
+        assert!(!root.join("older.bin").exists());
+
+        let older = fake_entry(root, "older", 10_000, 300);
+        let variant = only_variant(read_meta(&older).unwrap());
+        fs::remove_file(&variant.bin).unwrap();
+        write_meta(&older, &single_build(variant.fp, root.join("current.bin"))).unwrap();
+        evict_lru(root, &current, 0).unwrap();
+        assert!(!older.exists());
+        assert!(root.join("current.bin").exists());
+    }
+
+    #[test]
+    fn launcher_jobs_start_from_the_command_lines_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("tool.rs");
+        fs::write(&script, "fn main() {}").unwrap();
+        let launcher = |builder: LauncherBuilder| {
+            builder
+                .cache_dir(dir.path().join("cache"))
+                .build()
+                .job(&script)
+                .unwrap()
+        };
+
+        let job = launcher(Launcher::builder());
+        assert_eq!(job.build_retries, 1);
+        assert_eq!(job.hash_algo, HashAlgo::Blake3);
+        assert!(!job.report.quiet);
+        assert_eq!(job.settings.profile(), "release");
+
+        let job = launcher(Launcher::builder().quiet(true).profile("fast"));
+        assert!(job.report.quiet);
+        assert_eq!(job.settings.profile(), "fast");
+        assert_eq!(job.build_retries, 1);
+    }
+
+    #[test]
+    fn cargo_args_after_double_dash_go_last() {
+        let settings = BuildSettings {
+            profile: Profile::Debug,
+            cargo_args: vec!["--offline".into(), "--".into(), "-Wclippy::pedantic".into()],
+            ..test_settings()
+        };
+        let cmd = settings.clippy_command(Path::new("/tmp/script.rs"), true);
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "+nightly",
+                "-Zscript",
+                "clippy",
+                "--manifest-path",
+                "/tmp/script.rs",
+                "--offline",
+                "--message-format=json",
+                "--",
+                "-Wclippy::pedantic",
+            ]
+        );
+
+        // The shared target's own rustc flags join the user's rather than adding a second `--`.
+        let cmd = settings.shared_build_command(Path::new("/tmp/s.rs"), Path::new("/t"), "k", true);
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args[args.len() - 4..],
+            ["--", "-Wclippy::pedantic", "-C", "metadata=scriptr-k"]
+        );
+    }
+
+    #[test]
+    fn cargo_args_reach_build_command_and_fingerprint() {
+        let settings = BuildSettings {
+            cargo_args: vec!["--offline".into()],
+            ..test_settings()
+        };
+        let cmd = settings.build_command(Path::new("/tmp/script.rs"), false);
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "+nightly",
+                "-Zscript",
+                "build",
+                "--manifest-path",
+                "/tmp/script.rs",
+                "--offline",
+                "--message-format=json",
+                "--quiet",
+                "--release",
+            ]
+        );
+
+        let fp = settings.fingerprint(Sources {
+            mtime_ns: 0,
+            hash: String::new(),
+            algo: HashAlgo::Blake3,
+            modules: Vec::new(),
+        });
+        assert!(settings.matches(&fp));
+        let online = BuildSettings {
+            cargo_args: Vec::new(),
+            ..settings
+        };
+        assert!(!online.matches(&fp));
+    }
+
+    #[test]
+    fn binaries_in_the_other_profiles_dir_are_spotted() {
+        let bin = |path: &'static str| Path::new(path);
+        let release = bin("/t/release/script");
+        let debug = bin("/t/x86_64-unknown-linux-gnu/debug/script");
+        assert_eq!(Profile::Debug.foreign_dir(release), Some("release"));
+        assert_eq!(Profile::Release.foreign_dir(debug), Some("debug"));
+        assert_eq!(Profile::Release.foreign_dir(release), None);
+        assert_eq!(Profile::Debug.foreign_dir(debug), None);
+        // Stable copies and other profiles' dirs say nothing either way.
+        assert_eq!(Profile::Debug.foreign_dir(bin("/cache/bin/abc-def")), None);
+        assert_eq!(Profile::Small.foreign_dir(release), None);
+        assert_eq!(Profile::Named("fast".into()).foreign_dir(debug), None);
+    }
+
+    #[test]
+    fn mtime_keeps_subsecond_precision_and_old_entries_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("script.rs");
+        fs::write(&script, "fn main() {}").unwrap();
+        File::options()
+            .write(true)
+            .open(&script)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_millis(1_500))
+            .unwrap();
+        assert_eq!(mtime_nanos(&script).unwrap(), 1_500_000_000);
+
+        // A whole-seconds entry from an older version never matches a real nanosecond mtime.
+        let meta_path = dir.path().join("old.json");
+        let hash = "0".repeat(64);
+        fs::write(
+            &meta_path,
+            format!(r#"{{"fp":{{"mtime":1,"hash":"{hash}"}},"bin":"/x"}}"#),
+        )
+        .unwrap();
+        let old = only_variant(read_meta(&meta_path).unwrap());
+        assert_eq!(old.fp.mtime_ns, 0);
+    }
+
+    #[test]
+    fn with_edition_sets_the_package_edition() {
+        let edition = |src: &str| {
+            let src = with_edition(src, "2021").unwrap();
+            let manifest = embedded_manifest(&src).unwrap().unwrap();
+            let table: toml::Table = manifest.toml.parse().unwrap();
+            (
+                src,
+                table["package"]["edition"].as_str().unwrap().to_string(),
+            )
+        };
+
+        let (src, ed) = edition("#!/usr/bin/env scriptr\nfn main() {}\n");
+        assert_eq!(ed, "2021");
+        assert!(src.starts_with("#!/usr/bin/env scriptr\n---\n"), "{src}");
+        assert!(src.ends_with("---\nfn main() {}\n"), "{src}");
+
+        let (src, ed) = edition(
+            "----cargo\n[package]\nedition = \"2024\"\n[dependencies]\nx = \"1\"\n----\nfn main() {}",
+        );
+        assert_eq!(ed, "2021");
+        assert!(src.starts_with("----cargo\n"), "{src}");
+        assert!(src.contains("x = \"1\""), "{src}");
+        assert!(src.ends_with("\n----\nfn main() {}"), "{src}");
+
+        assert!(with_edition("---\npackage = 1\n---\n", "2021").is_err());
+        assert!(with_edition("---\n[package]\n", "2021").is_err());
+    }
+
+    #[test]
+    fn embedded_manifest_reads_frontmatter_and_doc_comments() {
+        let manifest = |src| embedded_manifest(src).unwrap();
+        assert_eq!(
+            manifest("#!/usr/bin/env scriptr\n\n----cargo\n[dependencies]\nx = \"1\"\n----\n"),
+            Some(EmbeddedManifest {
+                toml: "[dependencies]\nx = \"1\"".into(),
+                doc_comment: false,
+            })
+        );
+        assert_eq!(
+            manifest("//! Docs.\n//!\n//! ```cargo\n//! [dependencies]\n//! ```\nfn main() {}"),
+            Some(EmbeddedManifest {
+                toml: "[dependencies]".into(),
+                doc_comment: true,
+            })
+        );
+        assert_eq!(manifest("#![allow(unused)]\n---\n---\n"), None);
+        assert_eq!(manifest("fn main() {}\n---\n---\n"), None);
+        assert!(embedded_manifest("---\n[dependencies]\n--\n").is_err());
+    }
+
+    #[test]
+    fn package_files_skips_build_output_and_hidden_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for file in [
+            "Cargo.toml",
+            "Cargo.lock",
+            "build.rs",
+            "src/main.rs",
+            "src/bin/util/mod.rs",
+            "src/notes.md",
+            "target/debug/build/out.rs",
+            ".git/hooks.rs",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let files: Vec<_> = package_files(&root.join("Cargo.toml"))
+            .into_iter()
+            .map(|path| path.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            files,
+            [
+                "Cargo.lock",
+                "build.rs",
+                "src/bin/util/mod.rs",
+                "src/main.rs"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn module_files_finds_out_of_line_mods() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("helper.rs"), "").unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(dir.path().join("nested").join("mod.rs"), "").unwrap();
+        let script = dir.path().join("main.rs");
+        let src =
+            "mod helper;\npub(crate) mod nested;\nmod inline { }\nmod missing;\n// mod commented;";
+
+        assert_eq!(
+            module_files(&script, src),
+            [
+                dir.path().join("helper.rs"),
+                dir.path().join("nested").join("mod.rs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn io_errors_name_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.rs");
+        let err = format!("{:#}", file_hash(&missing, HashAlgo::Blake3).unwrap_err());
+        assert!(err.starts_with("failed to hash "), "{err}");
+        assert!(err.contains(&*missing.to_string_lossy()), "{err}");
+        let err = format!("{:#}", mtime_nanos(&missing).unwrap_err());
+        assert!(err.starts_with("failed to read the mtime of "), "{err}");
+        assert!(err.contains(&*missing.to_string_lossy()), "{err}");
+        // Missing is still an error, not a lack of mtime support.
+        assert!(mtime_if_supported(&missing).is_err());
+    }
+
+    #[test]
+    fn unsupported_mtimes_fall_back_to_hashing() {
+        let unsupported = Err(anyhow::Error::from(std::io::Error::from(
+            std::io::ErrorKind::Unsupported,
+        ))
+        .context("failed to read the mtime of x.rs"));
+        assert_eq!(unsupported_as_none(unsupported).unwrap(), None);
+        assert_eq!(unsupported_as_none(Ok(7)).unwrap(), Some(7));
+        let denied = Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied).into());
+        assert!(unsupported_as_none(denied).is_err());
+    }
+
+    #[test]
+    fn large_files_hash_the_same_via_mmap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("generated.rs");
+        let src: Vec<u8> = (0..MMAP_THRESHOLD as usize + 12_345)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        fs::write(&path, &src).unwrap();
+        assert_eq!(
+            file_hash(&path, HashAlgo::Blake3).unwrap(),
+            blake3::hash(&src).to_hex().as_str()
+        );
+    }
+
+    #[test]
+    fn timestamps_are_utc_rfc3339() {
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let later = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        assert_eq!(utc_timestamp(later), "2023-11-14T22:13:20.250Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(utc_timestamp(leap_day), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn each_hash_algo_round_trips_through_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("abc.rs");
+        fs::write(&script, "abc").unwrap();
+        let settings = test_settings();
+
+        for (algo, expected) in [
+            (
+                HashAlgo::Blake3,
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            ),
+            (
+                HashAlgo::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+        ] {
+            let fp = settings.fingerprint(fingerprint_sources(&script, algo).unwrap());
+            assert_eq!(fp.hash, expected);
+            let meta_path = dir.path().join(format!("{}.json", algo.name()));
+            write_meta(&meta_path, &single_build(fp, "/x".into())).unwrap();
+            let meta = only_variant(read_meta(&meta_path).unwrap());
+            assert_eq!(meta.fp.hash, expected);
+            assert_eq!(meta.fp.hash_algo, algo.name());
+        }
+
+        // Entries from before the algorithm was recorded were BLAKE3.
+        let old = dir.path().join("old.json");
+        let hash = "0".repeat(64);
+        fs::write(&old, format!(r#"{{"fp":{{"hash":"{hash}"}},"bin":"/x"}}"#)).unwrap();
+        let old = only_variant(read_meta(&old).unwrap());
+        assert_eq!(old.fp.hash_algo, "blake3");
+    }
+}
@@ -0,0 +1,3631 @@
+//! End-to-end tests that drive the `scriptr` binary against real single-file packages.
+//!
+//! These need `cargo +nightly` on PATH, just like scriptr itself.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::mpsc,
+    time::{Duration, SystemTime},
+};
+
+use tempfile::TempDir;
+
+/// A scratch directory holding scripts, with its own isolated scriptr cache.
+struct Sandbox {
+    dir: TempDir,
+}
+
+impl Sandbox {
+    fn new() -> Self {
+        Self {
+            dir: tempfile::tempdir().expect("create tempdir"),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    fn cache_dir(&self) -> PathBuf {
+        self.path().join("cache")
+    }
+
+    /// The config file scriptr reads; absent unless a test writes it.
+    fn config_path(&self) -> PathBuf {
+        self.path().join("config.toml")
+    }
+
+    /// Write a script named `name` whose body is `src`.
+    fn script(&self, name: &str, src: &str) -> PathBuf {
+        let path = self.path().join(name);
+        fs::write(&path, src).expect("write script");
+        path
+    }
+
+    /// Write an executable shell script named `name` whose body is `src`.
+    #[cfg(unix)]
+    fn executable(&self, name: &str, src: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = self.script(name, src);
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).expect("chmod script");
+        path
+    }
+
+    /// Every cache metadata file scriptr has written so far.
+    fn meta_files(&self) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(self.cache_dir()) else {
+            return Vec::new();
+        };
+        entries
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+            .collect()
+    }
+
+    /// A `scriptr` command whose cache lives inside this sandbox.
+    fn scriptr(&self) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_scriptr"));
+        cmd.env("SCRIPTR_CACHE_DIR", self.cache_dir())
+            .env("SCRIPTR_CONFIG", self.config_path());
+        cmd
+    }
+}
+
+#[test]
+fn propagates_exit_code_and_passthrough_args() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "exit_code.rs",
+        r#"fn main() {
+    let code: i32 = std::env::args().nth(1).unwrap().parse().unwrap();
+    std::process::exit(code);
+}
+"#,
+    );
+
+    let status = sb
+        .scriptr()
+        .arg(&script)
+        .arg("7")
+        .status()
+        .expect("run scriptr");
+    assert_eq!(status.code(), Some(7));
+}
+
+#[test]
+fn only_the_first_separator_is_consumed() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "dashes.rs",
+        "fn main() { println!(\"{:?}\", std::env::args().skip(1).collect::<Vec<_>>()); }\n",
+    );
+    let args = |args: &[&str]| {
+        let out = sb.scriptr().args(args).output().unwrap();
+        assert!(out.status.success(), "{out:?}");
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let path = script.to_str().unwrap();
+
+    assert_eq!(
+        args(&[path, "--", "a", "--", "b"]),
+        "[\"a\", \"--\", \"b\"]\n"
+    );
+    assert_eq!(args(&["--", path, "--", "a"]), "[\"--\", \"a\"]\n");
+    assert_eq!(args(&[path, "--", "--"]), "[\"--\"]\n");
+}
+
+#[test]
+fn run_and_build_subcommands() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "greet.rs",
+        r#"fn main() {
+    println!("{:?}", std::env::args().skip(1).collect::<Vec<_>>());
+}
+"#,
+    );
+    let output = |args: &[&str]| {
+        let out = sb.scriptr().args(args).output().unwrap();
+        assert!(out.status.success(), "{args:?}: {out:?}");
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let path = script.to_str().unwrap();
+
+    // Building runs nothing, but leaves the next run a cache hit.
+    assert_eq!(output(&["build", path]), "");
+    let explained = output(&["--explain", path]);
+    assert!(explained.contains("cache hit"), "{explained}");
+    assert_eq!(output(&["run", path, "-v", "x"]), "[\"-v\", \"x\"]\n");
+    assert_eq!(output(&["run", "--", path, "run"]), "[\"run\"]\n");
+    assert_eq!(output(&[path, "build"]), "[\"build\"]\n");
+
+    let extra = sb.scriptr().args(["build", path, "x"]).output().unwrap();
+    assert!(!extra.status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn argv0_is_the_script_name_unless_overridden() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "multi.rs",
+        "fn main() { println!(\"{}\", std::env::args().next().unwrap()); }\n",
+    );
+    let argv0 = |extra: &[&str], script: &Path| {
+        let out = sb.scriptr().args(extra).arg(script).output().unwrap();
+        assert!(out.status.success(), "{out:?}");
+        String::from_utf8(out.stdout).unwrap()
+    };
+    assert_eq!(argv0(&[], &script), "multi\n");
+    assert_eq!(argv0(&["--exec-arg0", "busybox"], &script), "busybox\n");
+    assert_eq!(
+        argv0(&["--exec-arg0", "busybox", "--time-limit", "30"], &script),
+        "busybox\n"
+    );
+
+    // Reached through a link, it goes by the link's name.
+    let link = sb.path().join("ls.rs");
+    std::os::unix::fs::symlink(&script, &link).unwrap();
+    assert_eq!(argv0(&[], &link), "ls\n");
+}
+
+#[test]
+fn cargo_packages_build_as_packages() {
+    let sb = Sandbox::new();
+    let package = sb.path().join("tiny");
+    fs::create_dir_all(package.join("src")).unwrap();
+    fs::write(
+        package.join("Cargo.toml"),
+        "[package]\nname = \"tiny\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+    )
+    .unwrap();
+    let main = package.join("src/main.rs");
+    fs::write(&main, "mod greet;\nfn main() { greet::hello(); }\n").unwrap();
+    fs::write(
+        package.join("src/greet.rs"),
+        "pub fn hello() { println!(\"hello\"); }\n",
+    )
+    .unwrap();
+    let run = |script: &Path| {
+        let out = sb.scriptr().arg("-v").arg(script).output().unwrap();
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        assert!(out.status.success(), "stderr: {stderr}");
+        (String::from_utf8(out.stdout).unwrap(), stderr)
+    };
+
+    let (stdout, stderr) = run(&package);
+    assert_eq!(stdout, "hello\n");
+    assert!(stderr.contains("Building script"), "stderr: {stderr}");
+    // The directory and its manifest are the same package, and it's cached.
+    let (stdout, stderr) = run(&package.join("Cargo.toml"));
+    assert_eq!(stdout, "hello\n");
+    assert!(!stderr.contains("Building script"), "stderr: {stderr}");
+
+    // Any of its sources invalidates the cache, not just the manifest.
+    fs::write(
+        package.join("src/greet.rs"),
+        "pub fn hello() { println!(\"hi\"); }\n",
+    )
+    .unwrap();
+    let (stdout, stderr) = run(&package);
+    assert_eq!(stdout, "hi\n");
+    assert!(stderr.contains("Building script"), "stderr: {stderr}");
+    fs::write(
+        &main,
+        "mod greet;\nfn main() { greet::hello(); greet::hello(); }\n",
+    )
+    .unwrap();
+    assert_eq!(run(&package).0, "hi\nhi\n");
+
+    let empty = sb.path().join("empty");
+    fs::create_dir(&empty).unwrap();
+    let out = sb.scriptr().arg(&empty).output().unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(
+        stderr.contains("directory without a Cargo.toml"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn print_path_outputs_binary_without_running_it() {
+    let sb = Sandbox::new();
+    let script = sb.script("noisy.rs", "fn main() { println!(\"ran\"); }\n");
+
+    let output = sb
+        .scriptr()
+        .arg("--print-path")
+        .arg(&script)
+        .output()
+        .expect("run scriptr");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let bin = PathBuf::from(stdout.trim_end());
+    assert!(bin.is_absolute(), "not absolute: {bin:?}");
+    assert!(bin.exists(), "missing binary: {bin:?}");
+
+    // A cache hit reports the same binary.
+    let again = sb.scriptr().arg("-p").arg(&script).output().unwrap();
+    assert_eq!(
+        String::from_utf8(again.stdout).unwrap().trim_end(),
+        bin.to_str().unwrap()
+    );
+}
+
+#[test]
+fn print_path_conflicts_with_clean_only() {
+    let sb = Sandbox::new();
+    let script = sb.script("conflict.rs", "fn main() {}\n");
+
+    let output = sb
+        .scriptr()
+        .args(["--print-path", "--clean-only"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--clean-only"), "{stderr}");
+}
+
+#[test]
+fn reads_script_from_stdin() {
+    let sb = Sandbox::new();
+    let src = r#"fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    println!("{}", args.join(","));
+}
+"#;
+
+    for _ in 0..2 {
+        let mut child = sb
+            .scriptr()
+            .args(["--", "-", "a", "b"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("spawn scriptr");
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(src.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a,b\n");
+    }
+}
+
+#[test]
+fn failed_stdin_runs_leave_no_staged_script() {
+    let sb = Sandbox::new();
+    let mut child = sb
+        .scriptr()
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"fn main() { not rust }\n")
+        .unwrap();
+    assert!(!child.wait().unwrap().success());
+    let left: Vec<_> = fs::read_dir(sb.cache_dir().join("stdin"))
+        .map(|dir| dir.map(|entry| entry.unwrap().file_name()).collect())
+        .unwrap_or_default();
+    assert!(left.is_empty(), "{left:?}");
+}
+
+#[test]
+fn script_reads_piped_stdin() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "prompt.rs",
+        r#"fn main() {
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).unwrap();
+    println!("got {}", line.trim());
+}
+"#,
+    );
+
+    // Both the exec path and the spawn-and-wait path of --time-limit.
+    for extra in [&[][..], &["--time-limit", "60"]] {
+        let mut child = sb
+            .scriptr()
+            .args(extra)
+            .arg(&script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("spawn scriptr");
+        child.stdin.take().unwrap().write_all(b"answer\n").unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{extra:?}");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "got answer\n");
+    }
+}
+
+#[test]
+fn eval_builds_and_runs_a_snippet() {
+    let sb = Sandbox::new();
+    let eval = |args: &[&str]| {
+        let out = sb.scriptr().args(args).output().unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(out.status.success(), "stderr: {stderr}");
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    assert_eq!(eval(&["--eval", r#"fn main(){println!("hi")}"#]), "hi\n");
+    assert_eq!(sb.meta_files().len(), 1);
+    // Identical code is the same cache entry.
+    assert_eq!(eval(&["-e", r#"fn main(){println!("hi")}"#]), "hi\n");
+    assert_eq!(sb.meta_files().len(), 1);
+
+    let args = r#"println!("{:?}", std::env::args().skip(1).collect::<Vec<_>>());"#;
+    assert_eq!(
+        eval(&["--wrap", "-e", args, "--", "-v", "x"]),
+        "[\"-v\", \"x\"]\n"
+    );
+}
+
+#[test]
+fn cache_clear_removes_only_what_scriptr_keeps() {
+    let sb = Sandbox::new();
+    for name in ["one.rs", "two.rs"] {
+        let script = sb.script(name, "fn main() {}\n");
+        assert!(sb.scriptr().arg(&script).status().unwrap().success());
+    }
+    let eval = sb.scriptr().args(["--wrap", "-e", "()"]).status().unwrap();
+    assert!(eval.success());
+    assert_eq!(sb.meta_files().len(), 3);
+    let unrelated = sb.cache_dir().join("notes.txt");
+    fs::write(&unrelated, "mine").unwrap();
+
+    let output = sb.scriptr().args(["cache", "clear"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout.contains("from 3 entries"), "{stdout}");
+    let left: Vec<_> = fs::read_dir(sb.cache_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name != ".checked")
+        .collect();
+    assert_eq!(left, ["notes.txt"]);
+}
+
+#[test]
+fn completions_are_printed_for_each_shell() {
+    let sb = Sandbox::new();
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let output = sb.scriptr().args(["completions", shell]).output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{shell}: {output:?}");
+        assert!(stdout.contains("scriptr"), "{shell}: {stdout}");
+    }
+    let unknown = sb.scriptr().args(["completions", "tcsh"]).output().unwrap();
+    assert!(!unknown.status.success());
+}
+
+#[test]
+fn gc_removes_orphans_after_a_dry_run() {
+    let sb = Sandbox::new();
+    let script = sb.script("doomed.rs", "fn main() {}\n");
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+    fs::remove_file(&script).unwrap();
+    let gc = |extra: &[&str]| {
+        let out = sb
+            .scriptr()
+            .args(["gc", "--orphans"])
+            .args(extra)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(out.status.success(), "stderr: {stderr}");
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    let preview = gc(&["--dry-run"]);
+    assert!(preview.contains("would remove "), "{preview}");
+    assert!(preview.contains("doomed.rs (script missing, "), "{preview}");
+    assert!(preview.contains("would reclaim "), "{preview}");
+    assert_eq!(sb.meta_files().len(), 1);
+
+    let removed = gc(&[]);
+    assert!(removed.contains("removed "), "{removed}");
+    assert!(removed.contains(" from 1 entries"), "{removed}");
+    assert!(sb.meta_files().is_empty());
+    assert!(gc(&[]).contains("reclaimed 0 B from 0 entries"));
+
+    // Without a policy there's nothing to do.
+    let out = sb.scriptr().arg("gc").output().unwrap();
+    assert!(!out.status.success());
+}
+
+#[test]
+fn print_cache_key_names_the_metadata_file() {
+    let sb = Sandbox::new();
+    let script = sb.script("keyed.rs", "fn main() {}\n");
+    let print_key = |extra: &[&str]| {
+        let out = sb
+            .scriptr()
+            .arg("--print-cache-key")
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    let printed = print_key(&[]);
+    assert!(
+        sb.meta_files().is_empty(),
+        "--print-cache-key mustn't build"
+    );
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+    let meta = sb.meta_files().pop().unwrap();
+    let key = meta.file_stem().unwrap().to_str().unwrap();
+    assert!(printed.contains(&format!("key: {key}\n")), "{printed}");
+    assert!(
+        printed.contains(&format!("metadata: {}\n", meta.display())),
+        "{printed}"
+    );
+    assert!(printed.contains("variant: release "), "{printed}");
+
+    // The profile picks a build within the entry; an ID picks another entry.
+    let debug = print_key(&["-d"]);
+    assert!(debug.contains(&format!("key: {key}\n")), "{debug}");
+    assert!(debug.contains("variant: debug "), "{debug}");
+    assert!(!print_key(&["--id", "other"]).contains(key));
+}
+
+#[test]
+fn scripts_build_clear_of_a_surrounding_project() {
+    let sb = Sandbox::new();
+    let src = "fn main() { println!(\"{}\", cfg!(ambient)); }\n";
+    // A project whose Cargo config would leak into builds run from inside it.
+    let project = |name: &str, manifest: bool| {
+        let dir = sb.path().join(name);
+        fs::create_dir_all(dir.join(".cargo")).unwrap();
+        fs::write(
+            dir.join(".cargo").join("config.toml"),
+            "[build]\nrustflags = [\"--cfg\", \"ambient\"]\n",
+        )
+        .unwrap();
+        if manifest {
+            fs::write(
+                dir.join("Cargo.toml"),
+                "[workspace]\nmembers = [\"nope\"]\n",
+            )
+            .unwrap();
+        }
+        fs::write(dir.join("tool.rs"), src).unwrap();
+        dir
+    };
+    let run = |dir: &Path, extra: &[&str]| {
+        let out = sb
+            .scriptr()
+            .current_dir(dir)
+            .args(extra)
+            .arg(dir.join("tool.rs"))
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(out.status.success(), "stderr: {stderr}");
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    // Beside a Cargo.toml, the script is isolated without being asked.
+    let workspace = project("workspace", true);
+    assert_eq!(run(&workspace, &[]), "false\n");
+
+    let loose = project("loose", false);
+    assert_eq!(run(&loose, &[]), "true\n");
+    assert_eq!(run(&loose, &["--isolated"]), "false\n");
+}
+
+#[test]
+fn edition_overrides_the_scripts_own() {
+    let sb = Sandbox::new();
+    // `gen` is a reserved keyword from the 2024 edition on.
+    let script = sb.script(
+        "edition.rs",
+        "---\n[package]\nedition = \"2024\"\n---\nfn main() { let gen = 21; println!(\"{gen}\"); }\n",
+    );
+    let run = |args: &[&str]| sb.scriptr().args(args).arg(&script).output().unwrap();
+
+    assert!(!run(&[]).status.success());
+    let out = run(&["--edition", "2021"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "21\n");
+    assert!(!run(&["--edition", "2024"]).status.success());
+
+    let out = run(&["--edition", "2022"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("2021"));
+}
+
+#[test]
+fn edition_builds_are_recorded_under_the_script() {
+    let sb = Sandbox::new();
+    let script = sb.script("e.rs", "fn main() {}\n");
+    let out = sb
+        .scriptr()
+        .args(["--edition", "2021"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{out:?}");
+
+    let info = sb
+        .scriptr()
+        .args(["cache", "info"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(info.status.success(), "{info:?}");
+    let list = sb.scriptr().args(["cache", "list"]).output().unwrap();
+    let list = String::from_utf8(list.stdout).unwrap();
+    assert!(
+        list.starts_with(&format!("{}\t", script.display())),
+        "{list}"
+    );
+
+    // Pruning the entry takes the edition's copy of the script with it.
+    let links = sb.cache_dir().join("links");
+    assert_eq!(fs::read_dir(&links).unwrap().count(), 1);
+    fs::remove_file(&script).unwrap();
+    let pruned = sb.scriptr().args(["cache", "prune"]).output().unwrap();
+    assert!(pruned.status.success(), "{pruned:?}");
+    assert!(String::from_utf8_lossy(&pruned.stdout).contains("script missing"));
+    assert!(sb.meta_files().is_empty());
+    assert_eq!(fs::read_dir(&links).unwrap().count(), 0);
+}
+
+#[test]
+fn companion_file_supplies_per_script_defaults() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "tuned.rs",
+        r#"---
+[features]
+loud = []
+---
+fn main() {
+    let greeting = std::env::var("GREETING").unwrap_or_default();
+    if cfg!(feature = "loud") {
+        println!("LOUD {greeting}");
+    } else {
+        println!("quiet {greeting}");
+    }
+}
+"#,
+    );
+    let companion = sb.path().join("tuned.rs.scriptr.toml");
+    fs::write(
+        &companion,
+        "features = [\"loud\"]\nenv = { GREETING = \"hi\" }\n",
+    )
+    .unwrap();
+    let run = |extra: &[&str]| {
+        let out = sb
+            .scriptr()
+            .arg("-v")
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        assert!(out.status.success(), "stderr: {stderr}");
+        (String::from_utf8(out.stdout).unwrap(), stderr)
+    };
+
+    let (stdout, stderr) = run(&[]);
+    assert_eq!(stdout, "LOUD hi\n");
+    assert!(stderr.contains("Loaded companion file"), "stderr: {stderr}");
+    let (stdout, stderr) = run(&[]);
+    assert_eq!(stdout, "LOUD hi\n");
+    assert!(!stderr.contains("Building script"), "stderr: {stderr}");
+
+    // The command line wins.
+    let (stdout, _) = run(&["--env", "GREETING=yo"]);
+    assert_eq!(stdout, "LOUD yo\n");
+
+    // Editing the companion rebuilds, even where the settings it gives don't change.
+    fs::write(
+        &companion,
+        "# still loud\nfeatures = [\"loud\"]\nenv = { GREETING = \"hi\" }\n",
+    )
+    .unwrap();
+    let (stdout, stderr) = run(&[]);
+    assert_eq!(stdout, "LOUD hi\n");
+    assert!(stderr.contains("Building script"), "stderr: {stderr}");
+    fs::write(&companion, "env = { GREETING = \"hi\" }\n").unwrap();
+    let (stdout, stderr) = run(&[]);
+    assert_eq!(stdout, "quiet hi\n");
+    assert!(stderr.contains("Building script"), "stderr: {stderr}");
+
+    fs::write(&companion, "featurez = []\n").unwrap();
+    let out = sb.scriptr().arg(&script).output().unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(
+        stderr.contains("invalid companion file"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn feature_sets_are_cached_separately() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "features.rs",
+        r#"---
+[features]
+loud = []
+---
+fn main() {
+    if cfg!(feature = "loud") {
+        println!("LOUD");
+    } else {
+        println!("quiet");
+    }
+}
+"#,
+    );
+    let run = |extra: &[&str]| {
+        let out = sb
+            .scriptr()
+            .arg("--verbose")
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        assert!(out.status.success(), "stderr: {stderr}");
+        (
+            String::from_utf8(out.stdout).unwrap(),
+            stderr.contains("Building script"),
+        )
+    };
+
+    assert_eq!(run(&[]), ("quiet\n".into(), true));
+    assert_eq!(run(&["--features", "loud"]), ("LOUD\n".into(), true));
+    assert_eq!(run(&[]), ("quiet\n".into(), false));
+    assert_eq!(run(&["-F", "loud"]), ("LOUD\n".into(), false));
+    assert_eq!(run(&["--all-features"]), ("LOUD\n".into(), true));
+
+    let out = sb
+        .scriptr()
+        .args(["-F", "bad!name"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("invalid feature name"), "stderr: {stderr}");
+}
+
+#[test]
+fn cached_binaries_survive_the_target_dir_being_wiped() {
+    let sb = Sandbox::new();
+    let script = sb.script("kept.rs", "fn main() { println!(\"still here\"); }\n");
+    let target = sb.path().join("target");
+    let output = sb
+        .scriptr()
+        .env("CARGO_TARGET_DIR", &target)
+        .arg("-p")
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let bin = PathBuf::from(String::from_utf8(output.stdout).unwrap().trim_end());
+    assert!(bin.starts_with(sb.cache_dir().join("bin")), "{bin:?}");
+    assert!(bin.is_file());
+
+    fs::remove_dir_all(&target).unwrap();
+    let output = sb
+        .scriptr()
+        .env("CARGO_TARGET_DIR", &target)
+        .arg("-v")
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "still here\n");
+    assert!(!stderr.contains("Building script"), "stderr: {stderr}");
+}
+
+#[test]
+fn debug_and_release_builds_are_distinct() {
+    let sb = Sandbox::new();
+    let script = sb.script("profiles.rs", "fn main() {}\n");
+    // Cargo's own output path names the profile; the copy under the cache doesn't.
+    let bin = |extra: &[&str]| {
+        let output = sb
+            .scriptr()
+            .args(["-p", "--raw-bin-path"])
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        PathBuf::from(String::from_utf8(output.stdout).unwrap().trim_end())
+    };
+
+    let debug = bin(&["--debug"]);
+    let release = bin(&[]);
+    assert_ne!(debug, release);
+    assert!(
+        debug.components().any(|c| c.as_os_str() == "debug"),
+        "{debug:?}"
+    );
+    assert!(
+        release.components().any(|c| c.as_os_str() == "release"),
+        "{release:?}"
+    );
+}
+
+#[test]
+fn corrupt_metadata_falls_back_to_rebuild() {
+    let sb = Sandbox::new();
+    let script = sb.script("corrupt.rs", "fn main() { println!(\"ok\"); }\n");
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+
+    let metas = sb.meta_files();
+    assert_eq!(metas.len(), 1);
+    fs::write(&metas[0], "{\"fp\": garbage").unwrap();
+
+    let output = sb.scriptr().arg(&script).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "ok\n");
+    assert!(
+        output.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn cache_list_info_and_prune() {
+    let sb = Sandbox::new();
+    let script = sb.script("cached.rs", "fn main() {}\n");
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+    let script = fs::canonicalize(script).unwrap();
+
+    let list = sb.scriptr().args(["cache", "list"]).output().unwrap();
+    assert!(list.status.success());
+    let list = String::from_utf8(list.stdout).unwrap();
+    assert!(list.starts_with(script.to_str().unwrap()), "{list}");
+
+    let info = sb
+        .scriptr()
+        .args(["cache", "info"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(info.status.success());
+    let info = String::from_utf8(info.stdout).unwrap();
+    assert!(info.contains("profile:   release"), "{info}");
+
+    fs::remove_file(&script).unwrap();
+    let prune = sb.scriptr().args(["cache", "prune"]).output().unwrap();
+    assert!(prune.status.success());
+    assert!(
+        String::from_utf8(prune.stdout)
+            .unwrap()
+            .contains("script missing")
+    );
+    assert!(sb.meta_files().is_empty());
+}
+
+#[test]
+fn prune_sweeps_stale_temp_files() {
+    let sb = Sandbox::new();
+    let eval = sb.scriptr().args(["--wrap", "-e", "()"]).status().unwrap();
+    assert!(eval.success());
+    let staged: Vec<_> = fs::read_dir(sb.cache_dir().join("eval"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    let half_written = sb
+        .cache_dir()
+        .join(format!("{}.json.1.0.0.new", "a".repeat(64)));
+    let orphan = sb.cache_dir().join("eval").join("eval_0123456789abcdef.rs");
+    let in_progress = sb
+        .cache_dir()
+        .join("eval")
+        .join("eval_fedcba9876543210.rs.2.new");
+    let long_ago = std::time::SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+    for path in [&half_written, &orphan, &staged[0]] {
+        fs::write(path, "").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(long_ago)
+            .unwrap();
+    }
+    fs::write(&in_progress, "").unwrap();
+
+    let prune = sb.scriptr().args(["cache", "prune"]).output().unwrap();
+    assert!(prune.status.success(), "{prune:?}");
+    let stdout = String::from_utf8(prune.stdout).unwrap();
+    assert_eq!(stdout.matches("(stray temp file)").count(), 2, "{stdout}");
+    assert!(!half_written.exists() && !orphan.exists());
+    // The snippet an entry was built from stays, as does a file too new to be abandoned.
+    assert!(staged[0].exists() && in_progress.exists());
+    assert_eq!(sb.meta_files().len(), 1);
+}
+
+#[test]
+fn builds_record_their_sizes_and_duration() {
+    let sb = Sandbox::new();
+    let src = "fn main() {}\n";
+    let script = sb.script("measured.rs", src);
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+
+    let meta_path = &sb.meta_files()[0];
+    let meta: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(meta_path).unwrap()).unwrap();
+    let variants = meta["variants"].as_object().unwrap();
+    let variant = variants.values().next().unwrap();
+    assert_eq!(variant["source_bytes"], src.len() as u64);
+    assert!(variant["build_ms"].as_u64().is_some(), "{variant}");
+    assert!(variant["bin_bytes"].as_u64().unwrap() > 0, "{variant}");
+
+    let info = sb
+        .scriptr()
+        .args(["cache", "info"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let info = String::from_utf8(info.stdout).unwrap();
+    assert!(info.contains("  source:    13 B"), "{info}");
+    assert!(info.contains("  build:     "), "{info}");
+    let list = sb.scriptr().args(["cache", "list"]).output().unwrap();
+    let list = String::from_utf8(list.stdout).unwrap();
+    assert_eq!(list.trim_end().split('\t').count(), 4, "{list}");
+    assert!(list.trim_end().ends_with('s'), "{list}");
+}
+
+#[test]
+fn log_file_takes_the_diagnostics_off_stderr() {
+    let sb = Sandbox::new();
+    let script = sb.script("logged.rs", "fn main() { eprintln!(\"from script\"); }\n");
+    let log = sb.path().join("scriptr.log");
+
+    for _ in 0..2 {
+        let out = sb
+            .scriptr()
+            .arg("--log-file")
+            .arg(&log)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        assert_eq!(String::from_utf8(out.stderr).unwrap(), "from script\n");
+    }
+
+    let log = fs::read_to_string(&log).unwrap();
+    for line in log.lines() {
+        let stamp = line.split(' ').next().unwrap();
+        assert!(stamp.ends_with('Z') && stamp.contains('T'), "{line}");
+    }
+    let hashes = log
+        .lines()
+        .filter(|l| l.contains(" [scriptr] phase cache lookup took"));
+    assert_eq!(hashes.count(), 2, "{log}");
+}
+
+#[test]
+fn no_progress_line_when_stderr_is_piped() {
+    let sb = Sandbox::new();
+    let script = sb.script("piped.rs", "fn main() { println!(\"done\"); }\n");
+    let out = sb.scriptr().arg(&script).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "done\n");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        !stderr.contains('\r') && !stderr.contains('\x1b'),
+        "{stderr:?}"
+    );
+    assert!(!stderr.contains("building piped.rs"), "{stderr:?}");
+}
+
+#[test]
+fn watch_reruns_after_edit() {
+    let sb = Sandbox::new();
+    let script = sb.script("watched.rs", "fn main() { println!(\"v1\"); }\n");
+
+    let mut child = sb
+        .scriptr()
+        .args(["--watch", "--watch-interval", "50"])
+        .arg(&script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let lines = line_channel(child.stdout.take().unwrap());
+
+    let next = || {
+        lines
+            .recv_timeout(Duration::from_secs(120))
+            .expect("output")
+    };
+    assert_eq!(next(), "v1");
+    fs::write(&script, "fn main() { println!(\"v2\"); }\n").unwrap();
+    assert_eq!(next(), "v2");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn sigterm_stops_a_watched_script_promptly() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "sleepy.rs",
+        r#"fn main() {
+    println!("{}", std::process::id());
+    std::thread::sleep(std::time::Duration::from_secs(120));
+}
+"#,
+    );
+
+    let mut child = sb
+        .scriptr()
+        .args(["--watch", "--watch-interval", "50"])
+        .arg(&script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let lines = line_channel(child.stdout.take().unwrap());
+    let script_pid = lines
+        .recv_timeout(Duration::from_secs(120))
+        .expect("script started");
+
+    let sent = std::time::Instant::now();
+    let kill = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if sent.elapsed() > Duration::from_secs(10) {
+            child.kill().unwrap();
+            panic!("scriptr still running 10s after SIGTERM");
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    assert_eq!(status.code(), Some(128 + 15), "status: {status:?}");
+    assert_eq!(status.signal(), None);
+
+    // The script got the signal as well, rather than being orphaned.
+    let alive = Command::new("kill")
+        .args(["-0", &script_pid])
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!alive.success(), "script {script_pid} outlived scriptr");
+}
+
+/// Forward each line of `reader` over a channel, so tests can wait on output with a timeout.
+fn line_channel(reader: impl std::io::Read + Send + 'static) -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            if tx.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+#[test]
+fn clean_that_cannot_remove_the_cache_warns_or_fails() {
+    let sb = Sandbox::new();
+    let script = sb.script("stuck.rs", "fn main() { println!(\"ran\"); }\n");
+    let run = |args: &[&str]| sb.scriptr().args(args).arg(&script).output().unwrap();
+    assert!(run(&[]).status.success());
+
+    // Permissions don't stop root, so a directory where the entry's failure record goes stands
+    // in for a file that can't be removed.
+    let stuck = sb.meta_files()[0].with_extension("failed");
+    fs::create_dir_all(stuck.join("inside")).unwrap();
+
+    let out = run(&["--clean", "--strict-clean", "-v"]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(stderr.contains("cannot clean the cache"), "{stderr}");
+    assert!(!stderr.contains("Building script"), "{stderr}");
+    assert!(out.stdout.is_empty());
+
+    fs::create_dir_all(stuck.join("inside")).unwrap();
+    let out = run(&["--clean", "-v"]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+    assert!(
+        stderr.contains("Warning: cannot clean the cache"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("rebuilding regardless"), "{stderr}");
+    assert!(stderr.contains("Building script"), "{stderr}");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "ran\n");
+
+    fs::remove_dir_all(&stuck).unwrap();
+    let out = run(&["--clean", "-v"]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Removed cache: "), "{stderr}");
+}
+
+#[test]
+fn run_in_sets_the_scripts_working_directory() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "where.rs",
+        "fn main() { println!(\"{}\", std::env::current_dir().unwrap().display()); }\n",
+    );
+    let elsewhere = sb.path().join("elsewhere");
+    fs::create_dir(&elsewhere).unwrap();
+
+    let out = sb
+        .scriptr()
+        .current_dir(sb.path())
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap().trim(),
+        sb.path().to_str().unwrap()
+    );
+
+    // Relative to where scriptr was started, and still the same cache entry.
+    let out = sb
+        .scriptr()
+        .current_dir(sb.path())
+        .args(["-v", "--run-in", "elsewhere"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+    assert!(stderr.contains("cache hit"), "{stderr}");
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap().trim(),
+        elsewhere.to_str().unwrap()
+    );
+
+    let out = sb
+        .scriptr()
+        .args(["--run-in", "/no/such/dir"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("no such directory"));
+}
+
+#[test]
+fn missing_binary_is_rebuilt_with_a_hint_if_that_fails() {
+    let sb = Sandbox::new();
+    let script = sb.script("cleaned.rs", "fn main() { println!(\"back\"); }\n");
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+    let meta: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&sb.meta_files()[0]).unwrap()).unwrap();
+    let variant = meta["variants"]
+        .as_object()
+        .unwrap()
+        .values()
+        .next()
+        .unwrap();
+    let bin = sb.cache_dir().join(variant["bin"].as_str().unwrap());
+
+    fs::remove_file(&bin).unwrap();
+    let out = sb.scriptr().arg("-v").arg(&script).output().unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+    assert!(
+        stderr.contains("is missing (deleted by `cargo clean`?)"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("Building script"), "{stderr}");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "back\n");
+    assert!(bin.is_file());
+
+    // A rebuild that fails after the binary went missing says it may be why.
+    fs::remove_file(&bin).unwrap();
+    fs::write(&script, "fn main() { broken }\n").unwrap();
+    let out = sb.scriptr().arg(&script).output().unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(101), "{stderr}");
+    assert!(
+        stderr.contains("the cached binary had been deleted"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn moved_cache_still_hits() {
+    let sb = Sandbox::new();
+    let script = sb.script("portable.rs", "fn main() { println!(\"portable\"); }\n");
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+
+    let moved = sb.path().join("restored");
+    fs::rename(sb.cache_dir(), &moved).unwrap();
+    let out = sb
+        .scriptr()
+        .env("SCRIPTR_CACHE_DIR", &moved)
+        .arg("-v")
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(out.status.success(), "{stderr}");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "portable\n");
+    assert!(stderr.contains("cache hit"), "{stderr}");
+    assert!(!stderr.contains("Building script"), "{stderr}");
+}
+
+#[test]
+fn cache_dir_flag_wins_over_env() {
+    let sb = Sandbox::new();
+    let script = sb.script("cache_dir.rs", "fn main() {}\n");
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+    assert_eq!(sb.meta_files().len(), 1);
+
+    let flag_dir = sb.path().join("flag-cache");
+    let status = sb
+        .scriptr()
+        .arg("--cache-dir")
+        .arg(&flag_dir)
+        .arg(&script)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let written: Vec<_> = fs::read_dir(&flag_dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    assert_eq!(written.len(), 1);
+    assert_eq!(sb.meta_files().len(), 1);
+}
+
+#[test]
+fn uncreatable_cache_dir_names_the_path() {
+    let sb = Sandbox::new();
+    let script = sb.script("blocked.rs", "fn main() {}\n");
+    let blocker = sb.script("not-a-dir", "");
+
+    let output = sb
+        .scriptr()
+        .env("SCRIPTR_CACHE_DIR", blocker.join("cache"))
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("cannot create cache directory"), "{stderr}");
+    assert!(stderr.contains("not-a-dir"), "{stderr}");
+}
+
+#[test]
+fn unusable_default_cache_dirs_are_all_listed() {
+    let sb = Sandbox::new();
+    let script = sb.script("homeless.rs", "fn main() {}\n");
+    let blocker = sb.script("not-a-dir", "");
+
+    let output = sb
+        .scriptr()
+        .env_remove("SCRIPTR_CACHE_DIR")
+        .env("XDG_CACHE_HOME", blocker.join("xdg"))
+        .env("HOME", blocker.join("home"))
+        .env("TMPDIR", blocker.join("tmp"))
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("no usable cache directory"),
+        "stderr: {stderr}"
+    );
+    for tried in ["xdg", "tmp"] {
+        let path = blocker.join(tried).join("scriptr");
+        assert!(
+            stderr.contains(&*path.to_string_lossy()),
+            "stderr: {stderr}"
+        );
+    }
+    assert!(stderr.contains("--cache-dir"), "stderr: {stderr}");
+}
+
+#[test]
+fn missing_cargo_and_toolchain_are_reported() {
+    let sb = Sandbox::new();
+    let script = sb.script("preflight.rs", "fn main() {}\n");
+
+    let no_cargo = sb
+        .scriptr()
+        .env("PATH", sb.path().join("empty"))
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!no_cargo.status.success());
+    let stderr = String::from_utf8(no_cargo.stderr).unwrap();
+    assert!(stderr.contains("cargo not found"), "{stderr}");
+
+    let no_toolchain = sb
+        .scriptr()
+        .args(["--toolchain", "scriptr-missing-toolchain"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!no_toolchain.status.success());
+    let stderr = String::from_utf8(no_toolchain.stderr).unwrap();
+    assert!(
+        stderr.contains("rustup toolchain install scriptr-missing-toolchain"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn shared_target_keeps_namesake_scripts_apart() {
+    let sb = Sandbox::new();
+    fs::create_dir_all(sb.path().join("a")).unwrap();
+    fs::create_dir_all(sb.path().join("b")).unwrap();
+    let a = sb.script("a/tool.rs", "fn main() { println!(\"a\"); }\n");
+    let b = sb.script("b/tool.rs", "fn main() { println!(\"b\"); }\n");
+
+    for (script, expected) in [(&a, "a\n"), (&b, "b\n"), (&a, "a\n")] {
+        let output = sb
+            .scriptr()
+            .arg("--shared-target")
+            .arg(script)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
+    assert!(sb.cache_dir().join("target").is_dir());
+    assert_eq!(fs::read_dir(sb.cache_dir().join("bin")).unwrap().count(), 2);
+}
+
+#[test]
+fn editing_a_module_file_rebuilds() {
+    let sb = Sandbox::new();
+    sb.script("helper.rs", "pub const VALUE: &str = \"one\";\n");
+    let script = sb.script(
+        "uses_mod.rs",
+        "mod helper;\nfn main() { println!(\"{}\", helper::VALUE); }\n",
+    );
+    let run = || {
+        let output = sb.scriptr().arg(&script).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(run(), "one\n");
+    sb.script("helper.rs", "pub const VALUE: &str = \"two\";\n");
+    assert_eq!(run(), "two\n");
+}
+
+#[test]
+fn json_forwards_cargo_messages_and_reports_cache_hits() {
+    let sb = Sandbox::new();
+    let script = sb.script("json.rs", "fn main() { println!(\"script output\"); }\n");
+    let run = || {
+        let output = sb.scriptr().arg("--json").arg(&script).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let fresh = run();
+    let lines: Vec<_> = fresh.lines().collect();
+    assert!(
+        lines
+            .iter()
+            .any(|l| l.contains(r#""reason":"compiler-artifact""#)),
+        "{fresh}"
+    );
+    assert!(
+        lines[lines.len() - 2].contains(r#""reason":"scriptr-ran""#),
+        "{fresh}"
+    );
+    assert_eq!(lines[lines.len() - 1], "script output");
+
+    let cached = run();
+    let lines: Vec<_> = cached.lines().collect();
+    assert_eq!(lines.len(), 2, "{cached}");
+    assert!(
+        lines[0].contains(r#""reason":"scriptr-cache-hit""#),
+        "{cached}"
+    );
+}
+
+#[test]
+fn quiet_prints_nothing_of_its_own() {
+    let sb = Sandbox::new();
+    let script = sb.script("quiet.rs", "fn main() { eprintln!(\"from script\"); }\n");
+
+    for _ in 0..2 {
+        let output = sb.scriptr().arg("-q").arg(&script).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "from script\n");
+    }
+
+    let both = sb
+        .scriptr()
+        .args(["-q", "-v"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!both.status.success());
+}
+
+#[test]
+fn build_failure_exits_with_cargos_code() {
+    let sb = Sandbox::new();
+    let script = sb.script("broken.rs", "fn main() { let x: u32 = \"nope\"; }\n");
+
+    let output = sb.scriptr().arg(&script).output().unwrap();
+    // cargo exits with 101 when compilation fails.
+    assert_eq!(output.status.code(), Some(101));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("mismatched types"), "{stderr}");
+
+    let missing = sb
+        .scriptr()
+        .arg(sb.path().join("missing.rs"))
+        .output()
+        .unwrap();
+    assert_eq!(missing.status.code(), Some(70));
+}
+
+#[test]
+fn alternating_profiles_run_the_matching_build() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "which.rs",
+        "fn main() { println!(\"{}\", cfg!(debug_assertions)); }\n",
+    );
+    for debug in [false, true, false, true] {
+        let mut cmd = sb.scriptr();
+        if debug {
+            cmd.arg("--debug");
+        }
+        let output = cmd.arg("--raw-bin-path").arg(&script).output().unwrap();
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("{debug}\n")
+        );
+
+        let mut cmd = sb.scriptr();
+        if debug {
+            cmd.arg("--debug");
+        }
+        let output = cmd
+            .args(["-p", "--raw-bin-path"])
+            .arg(&script)
+            .output()
+            .unwrap();
+        let bin = PathBuf::from(String::from_utf8(output.stdout).unwrap().trim_end());
+        let dir = if debug { "debug" } else { "release" };
+        assert_eq!(bin.parent().unwrap().file_name().unwrap(), dir, "{bin:?}");
+    }
+}
+
+#[test]
+fn default_profile_comes_from_the_environment() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "which.rs",
+        "fn main() { println!(\"{}\", cfg!(debug_assertions)); }\n",
+    );
+    let debug_build = |default: Option<&str>, extra: &[&str]| {
+        let mut cmd = sb.scriptr();
+        if let Some(default) = default {
+            cmd.env("SCRIPTR_DEFAULT_PROFILE", default);
+        }
+        let out = cmd.args(extra).arg(&script).output().unwrap();
+        assert!(out.status.success(), "{out:?}");
+        String::from_utf8(out.stdout).unwrap() == "true\n"
+    };
+
+    assert!(!debug_build(None, &[]));
+    assert!(debug_build(Some("debug"), &[]));
+    assert!(debug_build(None, &["--no-release"]));
+    // Flags always win, and the environment wins over the config file.
+    assert!(!debug_build(Some("debug"), &["--profile", "release"]));
+    fs::write(sb.config_path(), "debug = true\n").unwrap();
+    assert!(debug_build(None, &[]));
+    assert!(!debug_build(Some("release"), &[]));
+}
+
+#[test]
+fn config_file_supplies_defaults() {
+    let sb = Sandbox::new();
+    let script = sb.script("configured.rs", "fn main() {}\n");
+    fs::write(sb.config_path(), "debug = true\nverbose = true\n").unwrap();
+
+    let output = sb
+        .scriptr()
+        .args(["-p", "--raw-bin-path"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let bin = String::from_utf8(output.stdout).unwrap();
+    assert!(bin.contains("/debug/"), "{bin}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("[scriptr] Loaded config"), "{stderr}");
+
+    // Flags still win: --quiet overrides the config's verbose.
+    let quiet = sb
+        .scriptr()
+        .args(["-q", "-p"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(quiet.stderr.is_empty());
+}
+
+#[test]
+fn malformed_config_is_an_error_naming_the_file() {
+    let sb = Sandbox::new();
+    let script = sb.script("misconfigured.rs", "fn main() {}\n");
+    fs::write(sb.config_path(), "debug = \"yes please\"\n").unwrap();
+
+    let output = sb.scriptr().arg(&script).output().unwrap();
+    assert_eq!(output.status.code(), Some(70));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("invalid config"), "{stderr}");
+    assert!(stderr.contains("config.toml"), "{stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_as_shebang_interpreter_with_trailing_args() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sb = Sandbox::new();
+    let src = format!(
+        "#!{} -d -q\nfn main() {{\n    let args: Vec<String> = std::env::args().skip(1).collect();\n    println!(\"{{}}\", args.join(\"|\"));\n}}\n",
+        env!("CARGO_BIN_EXE_scriptr")
+    );
+    let script = sb.script("shebang.rs", &src);
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    // The kernel runs `scriptr "-d -q" /abs/shebang.rs --verbose two`.
+    let output = Command::new(&script)
+        .args(["--verbose", "two"])
+        .env("SCRIPTR_CACHE_DIR", sb.cache_dir())
+        .env("SCRIPTR_CONFIG", sb.config_path())
+        .output()
+        .expect("exec script");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "--verbose|two\n");
+    assert!(output.stderr.is_empty());
+
+    // A `--` straight after the path is scriptr's separator, shebang or not.
+    let run = |args: &[&str]| {
+        let output = Command::new(&script)
+            .args(args)
+            .env("SCRIPTR_CACHE_DIR", sb.cache_dir())
+            .env("SCRIPTR_CONFIG", sb.config_path())
+            .output()
+            .expect("exec script");
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(run(&["--", "x"]), "x\n");
+    assert_eq!(run(&["--", "--", "x"]), "--|x\n");
+
+    // The same invocation spelled out, as `#!/usr/bin/env scriptr` produces it.
+    let output = sb
+        .scriptr()
+        .arg(&script)
+        .args(["-x", "--", "three"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "-x|--|three\n");
+}
+
+#[test]
+fn no_cache_builds_outside_the_cache() {
+    let sb = Sandbox::new();
+    let script = sb.script("uncached.rs", "fn main() { println!(\"fresh\"); }\n");
+    // Keep the scratch dirs inside the sandbox.
+    let tmp = sb.path().join("tmp");
+    fs::create_dir(&tmp).unwrap();
+    let scriptr = || {
+        let mut cmd = sb.scriptr();
+        cmd.env("TMPDIR", &tmp).arg("--no-cache");
+        cmd
+    };
+
+    let output = scriptr().arg(&script).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "fresh\n");
+    assert!(sb.meta_files().is_empty());
+
+    let first = scriptr().arg("-p").arg(&script).output().unwrap();
+    let second = scriptr().arg("-p").arg(&script).output().unwrap();
+    let first = PathBuf::from(String::from_utf8(first.stdout).unwrap().trim_end());
+    let second = PathBuf::from(String::from_utf8(second.stdout).unwrap().trim_end());
+    assert!(first.exists(), "missing binary: {first:?}");
+    assert_ne!(first, second);
+    assert!(first.starts_with(&tmp), "{first:?}");
+    assert!(sb.meta_files().is_empty());
+
+    let clean = scriptr().arg("-c").arg(&script).output().unwrap();
+    assert!(!clean.status.success());
+}
+
+#[test]
+fn color_never_keeps_output_free_of_escapes() {
+    let sb = Sandbox::new();
+    let script = sb.script("plain.rs", "fn main() { let x: u32 = \"no\"; }\n");
+
+    for extra in [&["-v"][..], &[]] {
+        let output = sb
+            .scriptr()
+            .arg("--color=never")
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("mismatched types"), "{stderr}");
+        assert!(!stderr.contains('\x1b'), "{stderr:?}");
+    }
+
+    let output = sb
+        .scriptr()
+        .args(["--color=always", "-v"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("\x1b[36m[scriptr]"), "{stderr:?}");
+}
+
+#[test]
+fn concurrent_runs_of_one_script_build_once() {
+    let sb = Sandbox::new();
+    let script = sb.script("racy.rs", "fn main() {}\n");
+
+    let children: Vec<_> = (0..2)
+        .map(|_| {
+            sb.scriptr()
+                .args(["--json", "-p"])
+                .arg(&script)
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    let mut reasons: Vec<String> = children
+        .into_iter()
+        .map(|child| {
+            let output = child.wait_with_output().unwrap();
+            assert!(output.status.success());
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            ["scriptr-ran", "scriptr-cache-hit"]
+                .into_iter()
+                .find(|reason| stdout.contains(&format!("\"reason\":\"{reason}\"")))
+                .expect("scriptr status line")
+                .to_string()
+        })
+        .collect();
+    reasons.sort();
+    assert_eq!(reasons, ["scriptr-cache-hit", "scriptr-ran"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_scripts_follow_their_current_target() {
+    use std::os::unix::fs::symlink;
+
+    let sb = Sandbox::new();
+    let a = sb.script("a.rs", "fn main() { println!(\"a\"); }\n");
+    let b = sb.script("b.rs", "fn main() { println!(\"b\"); }\n");
+    // Identical mtimes, so only the link's target tells the two apart.
+    let mtime = fs::metadata(&a).unwrap().modified().unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&b)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+
+    let link = sb.path().join("current.rs");
+    symlink(&a, &link).unwrap();
+    let run = || String::from_utf8(sb.scriptr().arg(&link).output().unwrap().stdout).unwrap();
+    assert_eq!(run(), "a\n");
+
+    fs::remove_file(&link).unwrap();
+    symlink(&b, &link).unwrap();
+    assert_eq!(run(), "b\n");
+
+    fs::remove_file(&b).unwrap();
+    let output = sb.scriptr().arg(&link).output().unwrap();
+    assert_eq!(output.status.code(), Some(70));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("symlink target does not exist"), "{stderr}");
+    assert!(stderr.contains("b.rs"), "{stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn no_canonicalize_keys_each_link_separately() {
+    use std::os::unix::fs::symlink;
+
+    let sb = Sandbox::new();
+    let real = sb.script("real.rs", "fn main() { println!(\"real\"); }\n");
+    let link = sb.path().join("alias.rs");
+    symlink(&real, &link).unwrap();
+    let key = |extra: &[&str], script: &Path| {
+        let out = sb
+            .scriptr()
+            .args(extra)
+            .arg("--print-cache-key")
+            .arg(script)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{out:?}");
+        String::from_utf8(out.stdout)
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .to_string()
+    };
+
+    assert_eq!(key(&[], &link), key(&[], &real));
+    let lexical = key(&["--no-canonicalize"], &link);
+    assert_ne!(lexical, key(&["--no-canonicalize"], &real));
+    assert_ne!(lexical, key(&[], &link));
+
+    let out = sb
+        .scriptr()
+        .arg("--no-canonicalize")
+        .arg(&link)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "real\n");
+    fs::remove_file(&real).unwrap();
+    let out = sb
+        .scriptr()
+        .arg("--no-canonicalize")
+        .arg(&link)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("symlink target does not exist"), "{stderr}");
+}
+
+#[test]
+fn verify_spots_a_tampered_binary() {
+    let sb = Sandbox::new();
+    let script = sb.script("audited.rs", "fn main() { println!(\"ok\"); }\n");
+    let verify = || sb.scriptr().arg("--verify").arg(&script).output().unwrap();
+
+    let unbuilt = verify();
+    assert!(!unbuilt.status.success());
+    assert!(String::from_utf8_lossy(&unbuilt.stderr).contains("run it first"));
+
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+    let out = verify();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(out.status.success(), "{stdout}");
+    assert!(stdout.starts_with("verified: identical"), "{stdout}");
+
+    let bin = sb
+        .scriptr()
+        .arg("--print-path")
+        .arg(&script)
+        .output()
+        .unwrap()
+        .stdout;
+    let bin = PathBuf::from(String::from_utf8(bin).unwrap().trim_end());
+    let mut tampered = fs::OpenOptions::new().append(true).open(&bin).unwrap();
+    tampered.write_all(b"\0").unwrap();
+    drop(tampered);
+    let out = verify();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(!out.status.success());
+    assert!(stdout.starts_with("MISMATCH"), "{stdout}");
+    assert!(stdout.contains("changed after it was built"), "{stdout}");
+}
+
+#[test]
+fn trust_mtime_never_hashes() {
+    let sb = Sandbox::new();
+    let script = sb.script("trusted.rs", "fn main() {}\n");
+    let stderr = |extra: &[&str]| {
+        let output = sb
+            .scriptr()
+            .arg("-v")
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stderr).unwrap()
+    };
+    stderr(&[]);
+
+    let hit = stderr(&["--trust-mtime"]);
+    assert!(hit.contains("cache hit: mtime matches"), "{hit}");
+    assert!(!hit.contains("phase hash"), "{hit}");
+
+    // Same contents, new mtime: it rebuilds rather than hash to find nothing changed.
+    let touch = |secs| {
+        fs::File::options()
+            .write(true)
+            .open(&script)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap()
+    };
+    touch(1_000_000_000);
+    let miss = stderr(&["--trust-mtime"]);
+    assert!(
+        miss.contains("--trust-mtime skips the hash check"),
+        "{miss}"
+    );
+    assert!(miss.contains("Building script"), "{miss}");
+    assert!(!miss.contains("phase hash"), "{miss}");
+    touch(1_100_000_000);
+    let hashed = stderr(&[]);
+    assert!(hashed.contains("cache hit: hash matches"), "{hashed}");
+
+    let out = sb
+        .scriptr()
+        .args(["--trust-mtime", "--hash-only"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+}
+
+#[test]
+fn env_flag_reaches_the_script() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "echo_env.rs",
+        "fn main() { println!(\"{}\", std::env::var(\"FOO\").unwrap()); }\n",
+    );
+
+    let output = sb
+        .scriptr()
+        .args(["--env", "FOO=bar=baz"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "bar=baz\n");
+
+    // Runtime-only: a different value is still a cache hit.
+    let output = sb
+        .scriptr()
+        .args(["--json", "--env", "FOO=qux"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("\"reason\":\"scriptr-cache-hit\""),
+        "{stdout}"
+    );
+    assert!(stdout.ends_with("qux\n"), "{stdout}");
+}
+
+#[test]
+fn small_builds_a_separate_smaller_binary() {
+    let sb = Sandbox::new();
+    let script = sb.script("tiny.rs", "fn main() { println!(\"tiny\"); }\n");
+    let bin = |flags: &[&str]| {
+        let output = sb
+            .scriptr()
+            .args(flags)
+            .args(["-p", "--raw-bin-path"])
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        PathBuf::from(String::from_utf8(output.stdout).unwrap().trim_end())
+    };
+
+    let release = bin(&[]);
+    let small = bin(&["--small"]);
+    assert_ne!(release, small);
+    assert!(
+        small.to_str().unwrap().contains("scriptr-small"),
+        "{small:?}"
+    );
+    assert!(release.exists());
+    let size = |path: &Path| fs::metadata(path).unwrap().len();
+    assert!(size(&small) < size(&release));
+
+    let output = sb.scriptr().arg("--small").arg(&script).output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "tiny\n");
+    let conflict = sb
+        .scriptr()
+        .args(["--small", "-d"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!conflict.status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn cached_binary_that_cannot_be_exec_d_is_rebuilt() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sb = Sandbox::new();
+    let script = sb.script("fragile.rs", "fn main() { println!(\"recovered\"); }\n");
+    let output = sb.scriptr().arg("-p").arg(&script).output().unwrap();
+    let bin = PathBuf::from(String::from_utf8(output.stdout).unwrap().trim_end());
+
+    // Still there for the fast path, but exec fails with ENOENT, just as if it had been deleted
+    // between the check and the exec. (Replaced rather than overwritten: cargo hard-links it.)
+    fs::remove_file(&bin).unwrap();
+    fs::write(&bin, "#!/nonexistent/interpreter\n").unwrap();
+    fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = sb.scriptr().arg(&script).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "recovered\n");
+}
+
+#[test]
+fn args_file_lines_follow_the_command_line_args() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "argv.rs",
+        r#"fn main() {
+    for arg in std::env::args().skip(1) {
+        println!("<{arg}>");
+    }
+}
+"#,
+    );
+    let args_file = sb.path().join("args.txt");
+    fs::write(&args_file, "two words\n  'quoted' $HOME\n").unwrap();
+
+    let output = sb
+        .scriptr()
+        .arg("--args-file")
+        .arg(&args_file)
+        .arg(&script)
+        .arg("first")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "<first>\n<two words>\n<  'quoted' $HOME>\n"
+    );
+}
+
+#[test]
+fn build_warnings_are_counted_and_shown_on_request() {
+    let sb = Sandbox::new();
+    let script = sb.script("warns.rs", "fn main() {\n    let unused = 1;\n}\n");
+    let stderr = |extra: &[&str]| {
+        let output = sb
+            .scriptr()
+            .arg("-f")
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    let verbose = stderr(&["-v"]);
+    assert!(
+        verbose.contains("[scriptr] Build succeeded with 1 warning\n"),
+        "{verbose}"
+    );
+    assert!(!verbose.contains("unused variable"), "{verbose}");
+    assert!(!stderr(&[]).contains("unused variable"));
+    let shown = stderr(&["--show-warnings"]);
+    assert!(shown.contains("unused variable: `unused`"), "{shown}");
+}
+
+#[test]
+fn verbose_reports_phase_timings() {
+    let sb = Sandbox::new();
+    let script = sb.script("timed.rs", "fn main() {}\n");
+    let stderr = || {
+        let output = sb.scriptr().arg("-v").arg(&script).output().unwrap();
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    let miss = stderr();
+    assert!(miss.contains("[scriptr] phase cargo build took "), "{miss}");
+    assert!(
+        miss.contains("[scriptr] phase startup to exec took "),
+        "{miss}"
+    );
+
+    let hit = stderr();
+    assert!(hit.contains("[scriptr] phase cache lookup took "), "{hit}");
+    assert!(!hit.contains("phase cargo build"), "{hit}");
+
+    let quiet = sb.scriptr().arg(&script).output().unwrap();
+    assert!(quiet.stderr.is_empty());
+}
+
+#[test]
+fn check_reports_type_errors_without_touching_the_cache() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "typo.rs",
+        "fn main() { let unused = 1; let n: u32 = \"x\"; }\n",
+    );
+
+    let output = sb.scriptr().arg("--check").arg(&script).output().unwrap();
+    assert_eq!(output.status.code(), Some(101));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("mismatched types"), "{stderr}");
+    assert!(sb.meta_files().is_empty());
+
+    // A clean check exits 0, still shows warnings, and runs nothing.
+    fs::write(
+        &script,
+        "fn main() { let unused = 1; println!(\"ran\"); }\n",
+    )
+    .unwrap();
+    let output = sb.scriptr().arg("--check").arg(&script).output().unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unused variable"), "{stderr}");
+    assert!(sb.meta_files().is_empty());
+}
+
+#[test]
+fn clippy_surfaces_lints_with_extra_lint_args() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "lint.rs",
+        "fn main() { let v = vec![1]; if v.len() == 0 { println!(\"empty\"); } }\n",
+    );
+
+    let output = sb.scriptr().arg("--clippy").arg(&script).output().unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("clippy::len_zero"), "{stderr}");
+    assert!(sb.meta_files().is_empty());
+
+    // Lint flags after `--` reach clippy; denying a lint makes it fail.
+    let output = sb
+        .scriptr()
+        .args([
+            "--clippy",
+            "--cargo-arg=--",
+            "--cargo-arg=-Dclippy::len_zero",
+        ])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(101));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("error: length comparison to zero"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn lockfile_is_cached_and_locked_enforces_it() {
+    let sb = Sandbox::new();
+    let dep = sb.path().join("dep");
+    fs::create_dir_all(dep.join("src")).unwrap();
+    let write_dep = |version: &str| {
+        fs::write(
+            dep.join("Cargo.toml"),
+            format!("[package]\nname = \"dep\"\nversion = \"{version}\"\nedition = \"2021\"\n"),
+        )
+        .unwrap();
+    };
+    write_dep("0.1.0");
+    fs::write(dep.join("src/lib.rs"), "pub const N: u32 = 7;\n").unwrap();
+    let src = "---\n[dependencies]\ndep = { path = \"dep\" }\n---\n\nfn main() { println!(\"{}\", dep::N); }\n";
+    let script = sb.script("locked.rs", src);
+
+    let output = sb.scriptr().arg("--locked").arg(&script).output().unwrap();
+    assert!(!output.status.success(), "no lockfile to be locked to yet");
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+    let locks: Vec<_> = fs::read_dir(sb.cache_dir().join("locks"))
+        .unwrap()
+        .map(|e| e.unwrap().path().join("Cargo.lock"))
+        .collect();
+    assert_eq!(locks.len(), 1);
+    assert!(
+        fs::read_to_string(&locks[0])
+            .unwrap()
+            .contains("version = \"0.1.0\"")
+    );
+
+    // The dependency moves on; a rebuild with --locked refuses to re-resolve...
+    write_dep("0.2.0");
+    fs::write(&script, format!("{src}// edited\n")).unwrap();
+    let output = sb.scriptr().arg("--locked").arg(&script).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--locked"), "{stderr}");
+
+    // ...while a plain one updates the cached lockfile, and --clean-only removes it.
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+    assert!(
+        fs::read_to_string(&locks[0])
+            .unwrap()
+            .contains("version = \"0.2.0\"")
+    );
+    assert!(
+        sb.scriptr()
+            .arg("-C")
+            .arg(&script)
+            .status()
+            .unwrap()
+            .success()
+    );
+    assert!(!locks[0].exists());
+}
+
+#[test]
+fn explain_describes_the_decision_without_acting() {
+    let sb = Sandbox::new();
+    let script = sb.script("explained.rs", "fn main() { println!(\"ran\"); }\n");
+    let explain = |extra: &[&str]| {
+        let output = sb
+            .scriptr()
+            .arg("--explain")
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(explain(&[]), "cache miss: no cache found\n");
+    assert!(sb.meta_files().is_empty(), "--explain mustn't build");
+
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+    let hit = explain(&[]);
+    assert!(hit.contains("cached mtime: "), "{hit}");
+    assert!(hit.contains("cache hit: mtime matches\n"), "{hit}");
+    assert!(!hit.contains("ran"), "{hit}");
+    assert_eq!(explain(&["-f"]), "forced rebuild\n");
+
+    // Same content, new mtime: falls back to the hash.
+    fs::write(&script, fs::read(&script).unwrap()).unwrap();
+    assert!(explain(&[]).contains("cache hit: hash matches\n"));
+    fs::write(&script, "fn main() {}\n").unwrap();
+    let miss = explain(&[]);
+    assert!(miss.contains("cached hash: "), "{miss}");
+    assert!(miss.ends_with("cache miss: hash differs\n"), "{miss}");
+}
+
+#[test]
+fn dump_manifest_prints_the_frontmatter_without_building() {
+    let sb = Sandbox::new();
+    let manifest = "[dependencies]\nregex = { version = \"1\", default-features = false }";
+    let script = sb.script(
+        "deps.rs",
+        &format!("#!/usr/bin/env scriptr\n---\n{manifest}\n---\nfn main() {{}}\n"),
+    );
+    let output = sb
+        .scriptr()
+        .arg("--dump-manifest")
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{manifest}\n")
+    );
+    assert!(
+        output.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(sb.meta_files().is_empty(), "--dump-manifest mustn't build");
+
+    let bare = sb.script("bare.rs", "fn main() {}\n");
+    let output = sb
+        .scriptr()
+        .arg("--dump-manifest")
+        .arg(&bare)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("no embedded manifest"), "{stderr}");
+
+    let broken = sb.script("broken.rs", "---\n[dependencies\n---\nfn main() {}\n");
+    let output = sb
+        .scriptr()
+        .arg("--dump-manifest")
+        .arg(&broken)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"[dependencies\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("isn't valid TOML"), "{stderr}");
+}
+
+#[test]
+fn unknown_target_error_names_the_target() {
+    let sb = Sandbox::new();
+    let script = sb.script("cross.rs", "fn main() { println!(\"ran\"); }\n");
+
+    let output = sb
+        .scriptr()
+        .args(["--target", "bogus-unknown-none"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(101));
+    assert!(output.stdout.is_empty(), "nothing may run");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("build for target bogus-unknown-none failed"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("rustup target add bogus-unknown-none"),
+        "{stderr}"
+    );
+    assert!(sb.meta_files().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn transient_build_failure_is_retried() {
+    let sb = Sandbox::new();
+    let script = sb.script("busy.rs", "fn main() {}\n");
+    // Stands in for the built script.
+    let built = sb.executable("built.sh", "#!/bin/sh\necho built\n");
+
+    // A cargo whose first build loses a race with the linker.
+    let bin = sb.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let marker = sb.path().join("failed-once");
+    sb.executable(
+        "bin/cargo",
+        &format!(
+            r#"#!/bin/sh
+case " $* " in
+*" --version "*) echo "cargo 1.99.0-nightly (fake)"; exit 0 ;;
+esac
+if [ ! -e '{marker}' ]; then
+    : > '{marker}'
+    echo "error: failed to link: Text file busy (os error 26)" >&2
+    exit 101
+fi
+echo '{{"reason":"compiler-artifact","executable":"{built}"}}'
+"#,
+            marker = marker.display(),
+            built = built.display(),
+        ),
+    );
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+
+    let out = sb
+        .scriptr()
+        .env("PATH", &path)
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "stderr: {stderr}");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "built\n");
+    assert!(stderr.contains("retrying (1/1)"), "stderr: {stderr}");
+
+    // Without retries the same race is fatal.
+    fs::remove_file(&marker).unwrap();
+    let out = sb
+        .scriptr()
+        .env("PATH", &path)
+        .args(["--build-retries", "0", "--force"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(stderr.contains("Text file busy"), "stderr: {stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn cargo_override_is_used_for_every_cargo_call() {
+    let sb = Sandbox::new();
+    let script = sb.script("wrapped.rs", "fn main() {}\n");
+    let built = sb.executable("built.sh", "#!/bin/sh\necho built\n");
+    let log = sb.path().join("cargo.log");
+    let cargo = sb.executable(
+        "fake-cargo",
+        &format!(
+            r#"#!/bin/sh
+echo "$*" >> '{log}'
+case " $* " in
+*" --version "*) echo "cargo 1.99.0-nightly (fake)"; exit 0 ;;
+esac
+echo '{{"reason":"compiler-artifact","executable":"{built}"}}'
+"#,
+            log = log.display(),
+            built = built.display(),
+        ),
+    );
+
+    let out = sb
+        .scriptr()
+        .env("SCRIPTR_CARGO", &cargo)
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "built\n");
+    let calls = fs::read_to_string(&log).unwrap();
+    let calls: Vec<&str> = calls.lines().collect();
+    assert_eq!(calls.len(), 2, "calls: {calls:?}");
+    assert!(calls[0].ends_with("--version"), "calls: {calls:?}");
+    assert!(
+        calls[1].contains(&format!(
+            "-Zscript build --manifest-path {}",
+            script.display()
+        )),
+        "calls: {calls:?}"
+    );
+
+    // The flag wins over the environment, and must name something runnable.
+    let not_executable = sb.script("not-cargo", "");
+    let out = sb
+        .scriptr()
+        .env("SCRIPTR_CARGO", &cargo)
+        .arg("--cargo")
+        .arg(&not_executable)
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(
+        stderr.contains("is not an executable file"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn cache_failures_repeats_errors_until_the_script_changes() {
+    let sb = Sandbox::new();
+    let script = sb.script("broken.rs", "fn main() { let x: u32 = \"no\"; }\n");
+
+    let out = sb
+        .scriptr()
+        .arg("--cache-failures")
+        .arg(&script)
+        .output()
+        .unwrap();
+    let first = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(first.contains("mismatched types"), "stderr: {first}");
+
+    // A cargo that can't be found proves the second run never gets as far as a build.
+    let out = sb
+        .scriptr()
+        .args(["--cache-failures", "--cargo", "no-such-cargo"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(101), "stderr: {stderr}");
+    assert!(stderr.contains("repeating its errors"), "stderr: {stderr}");
+    assert!(stderr.contains("mismatched types"), "stderr: {stderr}");
+
+    // --force and fixing the script both build again.
+    let out = sb
+        .scriptr()
+        .args(["--cache-failures", "--force"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(!stderr.contains("repeating its errors"), "stderr: {stderr}");
+
+    fs::write(&script, "fn main() { println!(\"fixed\"); }\n").unwrap();
+    let out = sb
+        .scriptr()
+        .arg("--cache-failures")
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "fixed\n");
+}
+
+#[test]
+fn build_all_summarizes_and_fails_if_any_script_does() {
+    let sb = Sandbox::new();
+    let good = sb.script("good.rs", "fn main() { println!(\"never run\"); }\n");
+    let bad = sb.script("bad.rs", "fn main() { let x: u32 = \"no\"; }\n");
+
+    let out = sb
+        .scriptr()
+        .arg("--build-all")
+        .arg(&good)
+        .arg(&bad)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(101), "stderr: {stderr}");
+    assert!(!stdout.contains("never run"), "stdout: {stdout}");
+    assert!(
+        stdout.contains(&format!("built   {}", good.display())),
+        "stdout: {stdout}"
+    );
+    assert!(
+        stdout.contains(&format!("failed  {}", bad.display())),
+        "stdout: {stdout}"
+    );
+    assert!(
+        stdout.contains("2 scripts: 1 built, 0 cached, 1 failed"),
+        "stdout: {stdout}"
+    );
+    assert!(stderr.contains("mismatched types"), "stderr: {stderr}");
+    assert!(
+        stderr.contains("1 of 2 scripts failed to build"),
+        "stderr: {stderr}"
+    );
+
+    // The good script's binary is now cached.
+    let out = sb.scriptr().arg("--build-all").arg(&good).output().unwrap();
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("1 script: 0 built, 1 cached, 0 failed"));
+}
+
+#[test]
+fn changing_rustflags_invalidates_the_cache() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "flags.rs",
+        "fn main() { println!(\"{}\", if cfg!(scriptr_flag) { \"on\" } else { \"off\" }); }\n",
+    );
+    let run = |env: Option<&str>, flag: Option<&str>| {
+        let mut cmd = sb.scriptr();
+        cmd.env_remove("RUSTFLAGS");
+        if let Some(env) = env {
+            cmd.env("RUSTFLAGS", env);
+        }
+        if let Some(flag) = flag {
+            cmd.args(["--rustflags", flag]);
+        }
+        let out = cmd.arg(&script).output().unwrap();
+        assert!(
+            out.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    assert_eq!(run(None, None), "off\n");
+    assert_eq!(run(Some("--cfg scriptr_flag"), None), "on\n");
+    assert_eq!(run(None, None), "off\n");
+    assert_eq!(run(None, Some("--cfg scriptr_flag")), "on\n");
+}
+
+#[test]
+fn json_log_format_writes_one_object_per_line() {
+    let sb = Sandbox::new();
+    let script = sb.script("logged.rs", "fn main() { println!(\"out\"); }\n");
+    let build = sb
+        .scriptr()
+        .args(["--log-format=json", "--verbose"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(build.status.success());
+
+    // A cache hit, so everything on stderr is scriptr's own.
+    let out = sb
+        .scriptr()
+        .args(["--log-format=json", "--verbose"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "out\n");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    let logs: Vec<serde_json::Value> = stderr
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|err| panic!("{err}: {line}")))
+        .collect();
+    assert!(
+        logs.iter()
+            .all(|log| log["level"] == "debug" && log["msg"].is_string()),
+        "logs: {logs:?}"
+    );
+    assert!(
+        logs.iter()
+            .any(|log| log["msg"] == "cache hit: mtime matches"),
+        "logs: {logs:?}"
+    );
+    assert!(
+        logs.iter().any(|log| log["phase"] == "cache lookup"),
+        "logs: {logs:?}"
+    );
+}
+
+#[test]
+fn dedup_shares_one_binary_between_copies() {
+    let sb = Sandbox::new();
+    let src = "fn main() { println!(\"same\"); }\n";
+    fs::create_dir(sb.path().join("a")).unwrap();
+    fs::create_dir(sb.path().join("b")).unwrap();
+    let first = sb.script("a/tool.rs", src);
+    let second = sb.script("b/tool.rs", src);
+
+    for script in [&first, &second] {
+        let out = sb
+            .scriptr()
+            .args(["--dedup", "--verbose"])
+            .arg(script)
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "same\n");
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let reused = stderr.contains("Reusing binary built from identical sources");
+        let built = stderr.contains("Building script");
+        if script == &first {
+            assert!(built && !reused, "stderr: {stderr}");
+        } else {
+            assert!(reused && !built, "stderr: {stderr}");
+        }
+    }
+
+    assert_eq!(sb.meta_files().len(), 2);
+    let bins: Vec<_> = fs::read_dir(sb.cache_dir().join("bin"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(bins.len(), 1, "bins: {bins:?}");
+    // Recorded relative to the cache dir.
+    let bin = bins[0].strip_prefix(sb.cache_dir()).unwrap();
+    for meta in sb.meta_files() {
+        let meta = fs::read_to_string(meta).unwrap();
+        assert!(
+            meta.contains(&format!("\"{}\"", bin.display())),
+            "meta: {meta}"
+        );
+    }
+}
+
+#[test]
+fn content_keyed_copies_share_one_entry() {
+    let sb = Sandbox::new();
+    let src = "fn main() { println!(\"same\"); }\n";
+    fs::create_dir_all(sb.path().join("a")).unwrap();
+    fs::create_dir_all(sb.path().join("b")).unwrap();
+    let first = sb.script("a/tool.rs", src);
+    let second = sb.script("b/copy.rs", src);
+
+    for (script, hit) in [(&first, false), (&second, true)] {
+        let out = sb
+            .scriptr()
+            .args(["--content-keyed", "-v"])
+            .arg(script)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(out.status.success(), "stderr: {stderr}");
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "same\n");
+        assert!(stderr.contains("Cache key source: content"), "{stderr}");
+        assert_eq!(stderr.contains("cache hit"), hit, "{stderr}");
+    }
+    assert_eq!(sb.meta_files().len(), 1);
+}
+
+#[test]
+fn binary_from_another_builder_is_rebuilt() {
+    let sb = Sandbox::new();
+    let script = sb.script("versioned.rs", "fn main() {}\n");
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+
+    let meta_path = sb.meta_files().pop().unwrap();
+    let mut meta: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&meta_path).unwrap()).unwrap();
+    let variants = meta["variants"].as_object_mut().unwrap();
+    assert_eq!(variants.len(), 1);
+    let variant = variants.values_mut().next().unwrap();
+    let current = variant["builder_version"].as_str().unwrap().to_string();
+    assert!(current.starts_with("scriptr "), "builder: {current}");
+    assert!(current.contains("cargo "), "builder: {current}");
+    variant["builder_version"] = "scriptr 0.0.1, cargo 1.0.0-nightly".into();
+    fs::write(&meta_path, meta.to_string()).unwrap();
+
+    let out = sb.scriptr().arg("--verbose").arg(&script).output().unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "stderr: {stderr}");
+    assert!(
+        stderr.contains("built by scriptr 0.0.1, cargo 1.0.0-nightly; rebuilding"),
+        "stderr: {stderr}"
+    );
+    assert!(stderr.contains("Building script"), "stderr: {stderr}");
+    let meta = fs::read_to_string(&meta_path).unwrap();
+    assert!(meta.contains(&current), "meta: {meta}");
+}
+
+#[cfg(unix)]
+#[test]
+fn cache_hit_spawns_no_cargo() {
+    let sb = Sandbox::new();
+    let script = sb.script("quick.rs", "fn main() {}\n");
+    let built = sb.executable("built.sh", "#!/bin/sh\necho built\n");
+
+    // A cargo that records every invocation.
+    let bin = sb.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let calls = sb.path().join("cargo-calls");
+    let cargo = sb.executable(
+        "bin/cargo",
+        &format!(
+            r#"#!/bin/sh
+echo "$*" >> '{calls}'
+case " $* " in
+*" --version "*) echo "cargo 1.99.0-nightly (fake)"; exit 0 ;;
+esac
+echo '{{"reason":"compiler-artifact","executable":"{built}"}}'
+"#,
+            calls = calls.display(),
+            built = built.display(),
+        ),
+    );
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    let run = || {
+        let out = sb
+            .scriptr()
+            .env("PATH", &path)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{out:?}");
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "built\n");
+        let calls = fs::read_to_string(&calls).unwrap_or_default();
+        let _ = fs::remove_file(sb.path().join("cargo-calls"));
+        calls
+    };
+
+    let first = run();
+    assert!(first.contains("--version"), "calls: {first}");
+    assert!(first.contains("build"), "calls: {first}");
+    assert_eq!(run(), "");
+
+    // A different cargo is asked its version again, but still built the same binary.
+    let later = SystemTime::now() + Duration::from_secs(5);
+    fs::File::options()
+        .write(true)
+        .open(&cargo)
+        .unwrap()
+        .set_modified(later)
+        .unwrap();
+    let calls = run();
+    assert!(calls.contains("--version"), "calls: {calls}");
+    assert!(!calls.contains("build"), "calls: {calls}");
+    assert_eq!(run(), "");
+}
+
+#[test]
+fn rebuild_if_older_than_rebuilds_old_binaries() {
+    let sb = Sandbox::new();
+    let script = sb.script("aging.rs", "fn main() {}\n");
+    let run = |extra: &[&str]| {
+        let out = sb
+            .scriptr()
+            .arg("--verbose")
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        assert!(out.status.success(), "stderr: {stderr}");
+        stderr
+    };
+    run(&[]);
+
+    let meta_path = sb.meta_files().pop().unwrap();
+    let mut meta: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&meta_path).unwrap()).unwrap();
+    let variant = meta["variants"]
+        .as_object_mut()
+        .unwrap()
+        .values_mut()
+        .next()
+        .unwrap();
+    assert!(variant["built_at"].is_u64(), "meta: {meta}");
+    variant["built_at"] = (variant["built_at"].as_u64().unwrap() - 2 * 24 * 60 * 60).into();
+    fs::write(&meta_path, meta.to_string()).unwrap();
+
+    // Opt-in: without the flag, and with a long enough limit, the old binary is used.
+    assert!(!run(&[]).contains("Building script"));
+    assert!(!run(&["--rebuild-if-older-than", "7d"]).contains("Building script"));
+
+    let stale = run(&["--rebuild-if-older-than", "24h"]);
+    assert!(
+        stale.contains("over --rebuild-if-older-than's 86400s"),
+        "{stale}"
+    );
+    assert!(stale.contains("Building script"), "{stale}");
+    assert!(!run(&["--rebuild-if-older-than", "24h"]).contains("Building script"));
+}
+
+#[test]
+fn profile_flag_builds_a_custom_profile() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "profiled.rs",
+        r#"---
+[profile.fast]
+inherits = "release"
+opt-level = 1
+---
+fn main() { println!("fast"); }
+"#,
+    );
+
+    let out = sb
+        .scriptr()
+        .args(["--profile", "fast", "--print-path", "--raw-bin-path"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "stderr: {stderr}");
+    let bin = PathBuf::from(String::from_utf8_lossy(&out.stdout).trim());
+    assert_eq!(
+        bin.parent().unwrap().file_name().unwrap(),
+        "fast",
+        "bin: {}",
+        bin.display()
+    );
+    let out = sb
+        .scriptr()
+        .args(["--profile", "fast"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "fast\n");
+
+    let out = sb
+        .scriptr()
+        .args(["--profile", "fast", "--debug"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(stderr.contains("cannot be used with"), "stderr: {stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn script_path_with_invalid_utf8_builds_and_runs() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let sb = Sandbox::new();
+    // Latin-1 "café", which isn't UTF-8, as both a directory and a file name.
+    let dir = sb.path().join(OsStr::from_bytes(b"caf\xe9"));
+    fs::create_dir(&dir).unwrap();
+    let script = dir.join(OsStr::from_bytes(b"menu-\xe9.rs"));
+    fs::write(
+        &script,
+        "fn main() { println!(\"{:?}\", std::env::args().nth(1)); }\n",
+    )
+    .unwrap();
+
+    for _ in 0..2 {
+        let out = sb.scriptr().arg(&script).arg("x").output().unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(out.status.success(), "stderr: {stderr}");
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "Some(\"x\")\n");
+    }
+
+    let info = sb
+        .scriptr()
+        .args(["cache", "info"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(info.status.success(), "{info:?}");
+
+    // Pruning the entry takes the link it was built through with it.
+    let links = sb.cache_dir().join("links");
+    assert_eq!(fs::read_dir(&links).unwrap().count(), 1);
+    fs::remove_file(&script).unwrap();
+    let pruned = sb.scriptr().args(["cache", "prune"]).status().unwrap();
+    assert!(pruned.success());
+    assert_eq!(fs::read_dir(&links).unwrap().count(), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn install_copies_the_binary_out_of_the_cache() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sb = Sandbox::new();
+    let script = sb.script("frozen.rs", "fn main() { println!(\"frozen\"); }\n");
+    let dir = sb.path().join("bin");
+
+    let out = sb
+        .scriptr()
+        .arg("--install")
+        .arg(&dir)
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "stderr: {stderr}");
+    let installed = dir.join("frozen");
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout).trim_end(),
+        installed.to_str().unwrap()
+    );
+    let mode = fs::metadata(&installed).unwrap().permissions().mode();
+    assert!(mode & 0o111 != 0, "not executable: {mode:o}");
+
+    // It runs without scriptr, and outlives the cache.
+    fs::remove_dir_all(sb.cache_dir()).unwrap();
+    let ran = Command::new(&installed).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&ran.stdout), "frozen\n");
+
+    let again = sb
+        .scriptr()
+        .arg("--install")
+        .arg(&dir)
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&again.stderr);
+    assert!(!again.status.success());
+    assert!(stderr.contains("--force"), "stderr: {stderr}");
+
+    let forced = sb
+        .scriptr()
+        .args(["--force", "--install"])
+        .arg(&dir)
+        .arg(&script)
+        .status()
+        .unwrap();
+    assert!(forced.success());
+}
+
+#[test]
+fn stdin_name_keeps_one_entry_across_contents() {
+    let sb = Sandbox::new();
+    let pipe = |src: &str| {
+        let mut child = sb
+            .scriptr()
+            .args(["--json", "--stdin-name", "generated", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(src.as_bytes())
+            .unwrap();
+        let out = child.wait_with_output().unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let v1 = "fn main() { println!(\"v1\"); }\n";
+
+    let first = pipe(v1);
+    assert!(first.ends_with("v1\n"), "{first}");
+    assert!(!first.contains("scriptr-cache-hit"), "{first}");
+    let second = pipe(v1);
+    assert!(second.contains("\"scriptr-cache-hit\""), "{second}");
+    assert!(second.ends_with("v1\n"), "{second}");
+
+    // Different content under the same name rebuilds into the same entry.
+    let third = pipe("fn main() { println!(\"v2\"); }\n");
+    assert!(!third.contains("scriptr-cache-hit"), "{third}");
+    assert!(third.ends_with("v2\n"), "{third}");
+    assert_eq!(sb.meta_files().len(), 1);
+
+    let script = sb.script("named.rs", "fn main() {}\n");
+    let out = sb
+        .scriptr()
+        .args(["--stdin-name", "generated"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(stderr.contains("--stdin-name"), "stderr: {stderr}");
+}
+
+#[test]
+fn version_verbose_describes_the_environment() {
+    let sb = Sandbox::new();
+    let plain = sb.scriptr().arg("--version").output().unwrap();
+    assert!(plain.status.success());
+    let plain = String::from_utf8(plain.stdout).unwrap();
+    assert_eq!(plain, format!("scriptr {}\n", env!("CARGO_PKG_VERSION")));
+
+    let out = sb
+        .scriptr()
+        .args(["--version", "--verbose"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "stderr: {stderr}");
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], plain.trim_end());
+    assert!(lines.contains(&"toolchain: +nightly"), "{stdout}");
+    assert!(
+        lines.iter().any(|l| l.starts_with("cargo: cargo ")),
+        "{stdout}"
+    );
+    assert!(
+        lines.iter().any(|l| l.starts_with("rustc: rustc ")),
+        "{stdout}"
+    );
+    let cache = format!("cache: {}", sb.cache_dir().display());
+    assert!(lines.contains(&cache.as_str()), "{stdout}");
+    assert!(
+        lines
+            .iter()
+            .any(|l| l.starts_with("host: ") && l.contains('-')),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn alternating_debug_and_release_both_stay_cached() {
+    let sb = Sandbox::new();
+    let script = sb.script("alternating.rs", "fn main() {}\n");
+    let run = |extra: &[&str]| {
+        let out = sb
+            .scriptr()
+            .arg("--json")
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        String::from_utf8(out.stdout)
+            .unwrap()
+            .contains("\"scriptr-cache-hit\"")
+    };
+
+    assert!(!run(&["--debug"]));
+    assert!(!run(&[]));
+    for _ in 0..2 {
+        assert!(run(&["--debug"]), "debug build was evicted by release");
+        assert!(run(&[]), "release build was evicted by debug");
+    }
+    assert_eq!(sb.meta_files().len(), 1);
+
+    let info = sb
+        .scriptr()
+        .args(["cache", "info"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let info = String::from_utf8(info.stdout).unwrap();
+    assert_eq!(info.matches("variant:").count(), 2, "{info}");
+    assert!(info.contains("profile:   debug"), "{info}");
+    assert!(info.contains("profile:   release"), "{info}");
+}
+
+#[test]
+fn single_build_metadata_from_older_versions_still_hits() {
+    let sb = Sandbox::new();
+    let script = sb.script("legacy.rs", "fn main() {}\n");
+    assert!(sb.scriptr().arg(&script).status().unwrap().success());
+
+    // Rewrite the entry in the layout from before variants were tracked.
+    let meta_path = sb.meta_files().pop().unwrap();
+    let meta: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&meta_path).unwrap()).unwrap();
+    let mut legacy = meta["variants"]
+        .as_object()
+        .unwrap()
+        .values()
+        .next()
+        .unwrap()
+        .clone();
+    legacy["script"] = meta["script"].clone();
+    fs::write(&meta_path, legacy.to_string()).unwrap();
+
+    let out = sb.scriptr().arg("--explain").arg(&script).output().unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("cache hit: mtime matches"), "{stdout}");
+}
+
+#[cfg(unix)]
+#[test]
+fn edits_during_a_build_are_not_cached() {
+    let sb = Sandbox::new();
+    let script = sb.script("edited.rs", "fn main() {}\n");
+    let built = sb.executable("built.sh", "#!/bin/sh\necho built\n");
+    let log = sb.path().join("cargo.log");
+    let edited = sb.path().join("edited-once");
+    // The first build is interrupted by an edit, as if the user saved mid-build.
+    let cargo = sb.executable(
+        "fake-cargo",
+        &format!(
+            r#"#!/bin/sh
+case " $* " in
+*" --version "*) echo "cargo 1.99.0-nightly (fake)"; exit 0 ;;
+esac
+echo "$*" >> '{log}'
+if [ ! -e '{edited}' ]; then
+    touch '{edited}'
+    sleep 0.1
+    echo '// edited' >> '{script}'
+fi
+echo '{{"reason":"compiler-artifact","executable":"{built}"}}'
+"#,
+            log = log.display(),
+            edited = edited.display(),
+            script = script.display(),
+            built = built.display(),
+        ),
+    );
+    let run = || {
+        let out = sb
+            .scriptr()
+            .arg("--cargo")
+            .arg(&cargo)
+            .arg(&script)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        assert!(out.status.success(), "stderr: {stderr}");
+        assert_eq!(String::from_utf8(out.stdout).unwrap(), "built\n");
+        let builds = fs::read_to_string(&log).unwrap().lines().count();
+        (builds, stderr)
+    };
+
+    let (builds, stderr) = run();
+    assert_eq!(builds, 1);
+    assert!(
+        stderr.contains("changed while it was being built"),
+        "stderr: {stderr}"
+    );
+    assert!(sb.meta_files().is_empty(), "{:?}", sb.meta_files());
+
+    // The edited script builds again, and that build is cached.
+    assert_eq!(run().0, 2);
+    assert_eq!(run().0, 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn jobs_reach_cargo_but_not_the_fingerprint() {
+    let sb = Sandbox::new();
+    let script = sb.script("parallel.rs", "fn main() {}\n");
+    let built = sb.executable("built.sh", "#!/bin/sh\necho built\n");
+    let log = sb.path().join("cargo.log");
+    let cargo = sb.executable(
+        "fake-cargo",
+        &format!(
+            r#"#!/bin/sh
+case " $* " in
+*" --version "*) echo "cargo 1.99.0-nightly (fake)"; exit 0 ;;
+esac
+echo "$*" >> '{log}'
+echo '{{"reason":"compiler-artifact","executable":"{built}"}}'
+"#,
+            log = log.display(),
+            built = built.display(),
+        ),
+    );
+    let builds = |extra: &[&str]| {
+        let out = sb
+            .scriptr()
+            .arg("--cargo")
+            .arg(&cargo)
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let calls = fs::read_to_string(&log).unwrap_or_default();
+        calls.lines().map(str::to_string).collect::<Vec<_>>()
+    };
+
+    let calls = builds(&["--jobs", "2"]);
+    assert_eq!(calls.len(), 1, "calls: {calls:?}");
+    assert!(calls[0].contains(" --jobs 2"), "calls: {calls:?}");
+    // The same build without a limit is still a cache hit.
+    assert_eq!(builds(&[]).len(), 1);
+    let calls = builds(&["-f"]);
+    assert!(!calls[1].contains("--jobs"), "calls: {calls:?}");
+
+    let out = sb
+        .scriptr()
+        .args(["-j", "0"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn dry_run_prints_the_build_it_would_run() {
+    let sb = Sandbox::new();
+    let script = sb.script("rehearsed.rs", "fn main() {}\n");
+    let built = sb.executable("built.sh", "#!/bin/sh\necho built\n");
+    let log = sb.path().join("cargo.log");
+    let cargo = sb.executable(
+        "fake-cargo",
+        &format!(
+            r#"#!/bin/sh
+case " $* " in
+*" --version "*) echo "cargo 1.99.0-nightly (fake)"; exit 0 ;;
+esac
+echo "$*" >> '{log}'
+echo '{{"reason":"compiler-artifact","executable":"{built}"}}'
+"#,
+            log = log.display(),
+            built = built.display(),
+        ),
+    );
+    let run = |extra: &[&str]| {
+        let out = sb
+            .scriptr()
+            .arg("--cargo")
+            .arg(&cargo)
+            .args([
+                "--debug",
+                "--features",
+                "a,b",
+                "--cargo-arg=-Zunstable-options",
+            ])
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{out:?}");
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    let printed = run(&["--dry-run"]);
+    assert!(!log.exists(), "a dry run spawned cargo");
+    let (env, command) = printed
+        .trim_end()
+        .split_once(&*cargo.to_string_lossy())
+        .unwrap();
+    assert!(env.contains("CARGO_RESOLVER_LOCKFILE_PATH="), "{printed}");
+    run(&[]);
+    assert_eq!(
+        fs::read_to_string(&log).unwrap().trim_end(),
+        command.trim_start()
+    );
+
+    assert_eq!(run(&["--dry-run"]).lines().count(), 1);
+    assert!(!run(&["--dry-run"]).contains("fake-cargo"));
+    assert!(run(&["--dry-run", "-f"]).contains(" --features a,b"));
+}
+
+#[cfg(unix)]
+#[test]
+fn switching_cargo_home_rebuilds() {
+    let sb = Sandbox::new();
+    let script = sb.script("mirrored.rs", "fn main() {}\n");
+    let built = sb.executable("built.sh", "#!/bin/sh\necho built\n");
+    let log = sb.path().join("cargo.log");
+    let cargo = sb.executable(
+        "fake-cargo",
+        &format!(
+            r#"#!/bin/sh
+case " $* " in
+*" --version "*) echo "cargo 1.99.0-nightly (fake)"; exit 0 ;;
+esac
+echo "$*" >> '{log}'
+echo '{{"reason":"compiler-artifact","executable":"{built}"}}'
+"#,
+            log = log.display(),
+            built = built.display(),
+        ),
+    );
+    let mirror = sb.path().join("mirror-home");
+    fs::create_dir_all(&mirror).unwrap();
+    let builds = |home: &Path| {
+        let out = sb
+            .scriptr()
+            .env("CARGO_HOME", home)
+            .arg("--cargo")
+            .arg(&cargo)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        fs::read_to_string(&log).unwrap_or_default().lines().count()
+    };
+
+    let default_home = sb.path().join("cargo-home");
+    assert_eq!(builds(&default_home), 1);
+    assert_eq!(builds(&default_home), 1);
+    assert_eq!(builds(&mirror), 2);
+    assert_eq!(builds(&mirror), 2);
+    // Pointing the same home at a different registry touches its config.
+    fs::write(mirror.join("config.toml"), "[net]\nretry = 3\n").unwrap();
+    assert_eq!(builds(&mirror), 3);
+    assert_eq!(builds(&mirror), 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn offline_and_frozen_reach_cargo_and_the_fingerprint() {
+    let sb = Sandbox::new();
+    let script = sb.script("isolated.rs", "fn main() {}\n");
+    let built = sb.executable("built.sh", "#!/bin/sh\necho built\n");
+    let log = sb.path().join("cargo.log");
+    let cargo = sb.executable(
+        "fake-cargo",
+        &format!(
+            r#"#!/bin/sh
+case " $* " in
+*" --version "*) echo "cargo 1.99.0-nightly (fake)"; exit 0 ;;
+esac
+echo "$*" >> '{log}'
+echo '{{"reason":"compiler-artifact","executable":"{built}"}}'
+"#,
+            log = log.display(),
+            built = built.display(),
+        ),
+    );
+    let builds = |extra: &[&str]| {
+        let out = sb
+            .scriptr()
+            .arg("--cargo")
+            .arg(&cargo)
+            .args(extra)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let calls = fs::read_to_string(&log).unwrap_or_default();
+        calls.lines().map(str::to_string).collect::<Vec<_>>()
+    };
+
+    let calls = builds(&["--offline"]);
+    assert_eq!(calls.len(), 1, "calls: {calls:?}");
+    assert!(calls[0].contains(" --offline"), "calls: {calls:?}");
+    assert_eq!(
+        builds(&["--offline"]).len(),
+        1,
+        "offline build should be cached"
+    );
+
+    // An online build isn't the offline one, nor is a frozen one.
+    let calls = builds(&[]);
+    assert_eq!(calls.len(), 2, "calls: {calls:?}");
+    assert!(!calls[1].contains("--offline"), "calls: {calls:?}");
+    let calls = builds(&["--frozen"]);
+    assert_eq!(calls.len(), 3, "calls: {calls:?}");
+    assert!(calls[2].contains(" --frozen"), "calls: {calls:?}");
+}
+
+#[cfg(unix)]
+#[test]
+fn offline_build_missing_a_dependency_says_so() {
+    let sb = Sandbox::new();
+    let script = sb.script("needs_net.rs", "fn main() {}\n");
+    let cargo = sb.executable(
+        "fake-cargo",
+        r#"#!/bin/sh
+case " $* " in
+*" --version "*) echo "cargo 1.99.0-nightly (fake)"; exit 0 ;;
+esac
+echo "error: no matching package named \`left-pad\` found" >&2
+echo "note: offline mode (via \`--offline\`) can sometimes cause surprising resolution failures" >&2
+exit 101
+"#,
+    );
+
+    let out = sb
+        .scriptr()
+        .arg("--cargo")
+        .arg(&cargo)
+        .arg("--offline")
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(101), "stderr: {stderr}");
+    assert!(stderr.contains("no matching package"), "stderr: {stderr}");
+    assert!(stderr.contains("isn't downloaded"), "stderr: {stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn floods_of_cargo_output_on_both_streams_dont_deadlock() {
+    let sb = Sandbox::new();
+    let script = sb.script("chatty.rs", "fn main() {}\n");
+    let built = sb.executable("built.sh", "#!/bin/sh\necho built\n");
+    // Far more than a pipe buffer on each stream, interleaved.
+    let cargo = sb.executable(
+        "fake-cargo",
+        &format!(
+            r#"#!/bin/sh
+case " $* " in
+*" --version "*) echo "cargo 1.99.0-nightly (fake)"; exit 0 ;;
+esac
+i=0
+while [ $i -lt 5000 ]; do
+  echo "warning: unused thing number $i, padded out to make the line longer than it needs to be" >&2
+  echo '{{"reason":"build-script-executed","padding":"make this line longer than it needs to be"}}'
+  i=$((i + 1))
+done
+echo '{{"reason":"compiler-artifact","executable":"{built}"}}'
+"#,
+            built = built.display(),
+        ),
+    );
+
+    let mut cmd = sb.scriptr();
+    cmd.arg("--cargo").arg(&cargo).arg(&script);
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || tx.send(cmd.output().unwrap()).unwrap());
+    let out = rx
+        .recv_timeout(Duration::from_secs(60))
+        .expect("scriptr hung reading cargo's output");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "stderr: {stderr}");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "built\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn toolchain_without_script_support_is_explained() {
+    let sb = Sandbox::new();
+    let script = sb.script("unsupported.rs", "fn main() {}\n");
+    let cargo = sb.executable(
+        "fake-cargo",
+        r#"#!/bin/sh
+case " $* " in
+*" --version "*) echo "cargo 1.70.0-nightly (fake 2023-04-01)"; exit 0 ;;
+esac
+echo "error: unknown \`-Z\` flag specified: script" >&2
+exit 101
+"#,
+    );
+
+    let out = sb
+        .scriptr()
+        .arg("--cargo")
+        .arg(&cargo)
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(101), "stderr: {stderr}");
+    assert!(stderr.contains("unknown `-Z` flag"), "stderr: {stderr}");
+    assert!(
+        stderr.contains(
+            "toolchain +nightly (cargo 1.70.0-nightly (fake 2023-04-01)) doesn't support \
+             single-file packages"
+        ),
+        "stderr: {stderr}"
+    );
+    assert!(stderr.contains("rustup update nightly"), "stderr: {stderr}");
+}
+
+#[test]
+fn script_without_main_gets_a_targeted_error() {
+    let sb = Sandbox::new();
+    let script = sb.script("library.rs", "pub fn helper() -> u32 { 1 }\n");
+
+    let out = sb.scriptr().arg(&script).output().unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    // Still cargo's own failure, and exit code.
+    assert_eq!(out.status.code(), Some(101), "stderr: {stderr}");
+    assert!(stderr.contains("E0601"), "stderr: {stderr}");
+    assert!(
+        stderr.contains("single-file packages must define `fn main`"),
+        "stderr: {stderr}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn build_without_an_executable_is_not_a_cargo_failure() {
+    let sb = Sandbox::new();
+    let script = sb.script("artifactless.rs", "fn main() {}\n");
+    let cargo = sb.executable(
+        "fake-cargo",
+        r#"#!/bin/sh
+case " $* " in
+*" --version "*) echo "cargo 1.99.0-nightly (fake)"; exit 0 ;;
+esac
+echo '{"reason":"build-finished","success":true}'
+"#,
+    );
+
+    let out = sb
+        .scriptr()
+        .arg("--cargo")
+        .arg(&cargo)
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(70), "stderr: {stderr}");
+    assert!(
+        stderr.contains("cargo build succeeded, but script produced no executable"),
+        "stderr: {stderr}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn since_rebuilds_scripts_changed_from_a_git_revision() {
+    let sb = Sandbox::new();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(sb.path())
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?}");
+    };
+    let script = sb.script("tracked.rs", "fn main() { println!(\"v1\"); }\n");
+    git(&["init", "-q"]);
+    git(&["add", "tracked.rs"]);
+    git(&["commit", "-q", "-m", "v1"]);
+    sb.script("tracked.rs", "fn main() { println!(\"v2\"); }\n");
+    let run = |extra: &[&str], script: &Path| {
+        let out = sb
+            .scriptr()
+            .arg("-v")
+            .args(extra)
+            .arg(script)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{out:?}");
+        String::from_utf8_lossy(&out.stderr).into_owned()
+    };
+    run(&[], &script);
+
+    // The cache is current, so only --since notices the script moved on from HEAD.
+    assert!(!run(&[], &script).contains("Building script"));
+    let stderr = run(&["--since", "HEAD"], &script);
+    assert!(stderr.contains("changed since HEAD"), "stderr: {stderr}");
+    assert!(stderr.contains("Building script"), "stderr: {stderr}");
+
+    // Outside a repository it warns and falls back to the usual checks.
+    let outside = TempDir::new().unwrap();
+    let loose = outside.path().join("loose.rs");
+    fs::write(&loose, "fn main() {}\n").unwrap();
+    run(&[], &loose);
+    let stderr = run(&["--since", "HEAD"], &loose);
+    assert!(stderr.contains("Warning: --since HEAD"), "stderr: {stderr}");
+    assert!(!stderr.contains("Building script"), "stderr: {stderr}");
+}
+
+#[test]
+fn time_limit_stops_a_hanging_script() {
+    let sb = Sandbox::new();
+    let script = sb.script(
+        "hangs.rs",
+        "fn main() { std::thread::sleep(std::time::Duration::from_secs(30)); }\n",
+    );
+    // Build first, so the limit only covers the run.
+    assert!(
+        sb.scriptr()
+            .arg("-p")
+            .arg(&script)
+            .status()
+            .unwrap()
+            .success()
+    );
+
+    let started = std::time::Instant::now();
+    let out = sb
+        .scriptr()
+        .args(["--time-limit", "1"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(124), "stderr: {stderr}");
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert!(stderr.contains("time limit"), "stderr: {stderr}");
+
+    // A script that finishes in time keeps its own exit code.
+    let quick = sb.script("quick.rs", "fn main() { std::process::exit(3); }\n");
+    let status = sb
+        .scriptr()
+        .args(["--time-limit", "30"])
+        .arg(&quick)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(3));
+}
+
+#[test]
+fn status_file_reports_build_then_cache_hit() {
+    let sb = Sandbox::new();
+    let script = sb.script("status.rs", "fn main() {}\n");
+    let status_file = sb.path().join("status.json");
+    let status = || {
+        let out = sb
+            .scriptr()
+            .arg("--status-file")
+            .arg(&status_file)
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let text = fs::read_to_string(&status_file).unwrap();
+        serde_json::from_str::<serde_json::Value>(&text).unwrap()
+    };
+
+    let fresh = status();
+    assert_eq!(fresh["cache"], "miss", "{fresh}");
+    assert_eq!(fresh["rebuilt"], true, "{fresh}");
+    assert!(fresh["build_ms"].is_u64(), "{fresh}");
+    let bin = PathBuf::from(fresh["bin"].as_str().unwrap());
+    assert!(bin.is_file(), "{fresh}");
+
+    let hit = status();
+    assert_eq!(hit["cache"], "hit", "{hit}");
+    assert_eq!(hit["rebuilt"], false, "{hit}");
+    assert_eq!(hit["bin"], fresh["bin"], "{hit}");
+}
+
+#[test]
+fn extensionless_script_builds_with_its_modules() {
+    let sb = Sandbox::new();
+    sb.script("helper.rs", "pub const GREETING: &str = \"hello\";\n");
+    let script = sb.script(
+        "greet",
+        "#!/usr/bin/env scriptr\nmod helper;\nfn main() { println!(\"{}\", helper::GREETING); }\n",
+    );
+
+    for _ in 0..2 {
+        let out = sb.scriptr().arg(&script).output().unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(out.status.success(), "stderr: {stderr}");
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "hello\n");
+    }
+    // Cargo takes the file as it is, so no `.rs` link is needed.
+    assert!(!sb.cache_dir().join("links").exists());
+}
+
+/// Scripts need nightly cargo, but scriptr itself builds on stable Rust back to its
+/// `rust-version`: check it with each of those toolchains that's installed, skipping the rest.
+#[test]
+fn builds_on_stable_and_the_minimum_rust_version() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let manifest = fs::read_to_string(root.join("Cargo.toml")).unwrap();
+    let msrv = manifest
+        .lines()
+        .find_map(|line| line.strip_prefix("rust-version = "))
+        .expect("Cargo.toml sets rust-version")
+        .trim_matches('"');
+    for toolchain in ["stable", msrv] {
+        // Without this, rustup would download the toolchain just to answer.
+        let installed = Command::new("cargo")
+            .arg(format!("+{toolchain}"))
+            .arg("--version")
+            .env("RUSTUP_AUTO_INSTALL", "0")
+            .output()
+            .is_ok_and(|out| out.status.success());
+        if !installed {
+            eprintln!("skipping {toolchain}: not installed");
+            continue;
+        }
+        let out = Command::new("cargo")
+            .arg(format!("+{toolchain}"))
+            .args(["check", "--locked", "--all-targets", "--manifest-path"])
+            .arg(root.join("Cargo.toml"))
+            .env(
+                "CARGO_TARGET_DIR",
+                Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("check-{toolchain}")),
+            )
+            .env_remove("RUSTUP_TOOLCHAIN")
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "cargo +{toolchain} check failed:\n{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+}